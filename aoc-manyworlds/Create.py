@@ -1,3 +1,13 @@
+# past the latin alphabet, keys carry on with greek and cyrillic letters
+# whose upper and lower case can't be mistaken for a latin key or door
+KEY_ALPHABET = "abcdefghijklmnopqrstuvwxyzγδθλξπσφψωбджзийцчшщъыьэюя"
+
+def max_keys(width, height):
+    # every cell of a quadrant's maze and the passage behind it can hold a key or a door,
+    # apart from the robot's own cell
+    spots = 4 * (2 * ((width // 2) // 2) * ((height // 2) // 2) - 2)
+    return min(len(KEY_ALPHABET), spots // 2)
+
 class Cell:
     cellType = "Wall"
     player = 0
@@ -26,16 +36,146 @@ class Grid:
     cart = []
     tree: dict()#HashMap<(i16, i16), Option<(i16, i16)>>,
 
-    def __init__(self):
+    def __init__(self, width=81, height=81):
+        self.width = width
+        self.height = height
         self.cart = []
-        for i in range(81):
+        for i in range(height):
             row = []
-            for j in range(81):
+            for j in range(width):
                 row.append(Cell("Wall"))
             self.cart.append(row)
         self.tree = dict()
 
-    def carve(self, world, x, y, dx, dy):
+    def centre(self):
+        return (self.width // 2, self.height // 2)
+
+    def starts(self):
+        (cx, cy) = self.centre()
+        return [(cx - 1, cy - 1), (cx + 1, cy - 1), (cx - 1, cy + 1), (cx + 1, cy + 1)]
+
+    def carve(self, world, x, y, dx, dy, algorithm="backtracker"):
+        if algorithm == "backtracker":
+            return self.backtrack(world, x, y, dx, dy)
+        cells = self.quadrant_cells(x, y, dx, dy)
+        passages = maze_passages(world, algorithm, cells, (x, y))
+
+        self.cart[y][x] = Cell("Empty")
+        self.tree[(x, y)] = None
+        links = dict()
+        for (a, b) in passages:
+            links.setdefault(a, []).append(b)
+            links.setdefault(b, []).append(a)
+        queue = [(x, y)]
+        while len(queue) > 0:
+            (xc, yc) = queue.pop(0)
+            for (xn, yn) in links.get((xc, yc), []):
+                (xw, yw) = ((xc + xn) // 2, (yc + yn) // 2)
+                if (xw, yw) in self.tree:
+                    continue
+                self.tree[(xw, yw)] = (xc, yc)
+                self.tree[(xn, yn)] = (xw, yw)
+                self.cart[yw][xw] = Cell("Empty")
+                self.cart[yn][xn] = Cell("Empty")
+                queue.append((xn, yn))
+
+    # opens up a dead end into a loop with a chance of percent in 100
+    def braid(self, world, percent):
+        (cx, cy) = self.centre()
+        for (x, y) in self.starts():
+            cells = self.quadrant_cells(x, y, x > cx, y > cy)
+            cellset = set(cells)
+            for (xc, yc) in cells:
+                walls = [((xc + xn) // 2, (yc + yn) // 2) for (xn, yn) in cell_neighbours((xc, yc)) if (xn, yn) in cellset]
+                walls = [(xw, yw) for (xw, yw) in walls if self.cart[yw][xw].cellType == "Wall"]
+                exits = [(xn, yn) for (xn, yn) in [(xc + 1, yc), (xc, yc + 1), (xc - 1, yc), (xc, yc - 1)] if self.cart[yn][xn].cellType != "Wall"]
+                if len(exits) == 1 and world.random.randrange(100) < percent and len(walls) > 0:
+                    (xw, yw) = world.random.choice(walls)
+                    self.cart[yw][xw] = Cell("Empty")
+
+    def open_neighbours(self, c):
+        (x, y) = c
+        return [(xn, yn) for (xn, yn) in [(x + 1, y), (x, y + 1), (x - 1, y), (x, y - 1)]
+                if 0 <= xn < self.width and 0 <= yn < self.height and self.cart[yn][xn].cellType != "Wall"]
+
+    # the spot every route from the robots to each open spot has to go through last.
+    # in a perfect maze that's just the parent in the tree, but loops give other ways round
+    def dominators(self, starts):
+        postorder = []
+        seen = set()
+        for start in starts:
+            seen.add(start)
+            stack = [(start, 0)]
+            while len(stack) > 0:
+                (c, i) = stack.pop()
+                if i == 4:
+                    postorder.append(c)
+                    continue
+                stack.append((c, i + 1))
+                (x, y) = c
+                n = [(x + 1, y), (x, y + 1), (x - 1, y), (x, y - 1)][i]
+                if n in self.open_neighbours(c) and n not in seen:
+                    seen.add(n)
+                    stack.append((n, 0))
+        number = {c: i for i, c in enumerate(postorder)}
+
+        # cooper, harvey and kennedy's iterative algorithm
+        idom = {s: s for s in starts}
+        changed = True
+        while changed:
+            changed = False
+            for c in reversed(postorder):
+                if c in starts:
+                    continue
+                new = None
+                for p in self.open_neighbours(c):
+                    if p not in idom:
+                        continue
+                    if new is None:
+                        new = p
+                        continue
+                    (a, b) = (new, p)
+                    while a != b:
+                        while number[a] < number[b]:
+                            a = idom[a]
+                        while number[b] < number[a]:
+                            b = idom[b]
+                    new = a
+                if new is not None and idom.get(c) != new:
+                    idom[c] = new
+                    changed = True
+        return {c: (None if c == d else d) for c, d in idom.items()}
+
+    # the doors (as bits in KEY_ALPHABET order) on every route from the robots to each spot,
+    # leaving out routes whose doors are a superset of another route's
+    def door_routes(self, starts, doormap):
+        bits = {p: 1 << KEY_ALPHABET.index(d) for p, d in doormap.items()}
+        routes = dict()
+        queue = []
+        for s in starts:
+            routes[s] = [0]
+            queue.append((s, 0))
+        i = 0
+        while i < len(queue):
+            (c, doors) = queue[i]
+            i += 1
+            for n in self.open_neighbours(c):
+                passed = doors | bits.get(n, 0)
+                here = routes.setdefault(n, [])
+                if any(r & passed == r for r in here):
+                    continue
+                here[:] = [r for r in here if r & passed != passed]
+                here.append(passed)
+                queue.append((n, passed))
+        return routes
+
+    # every cell a quadrant's maze can visit, starting from (x, y) and growing towards (dx, dy)
+    def quadrant_cells(self, x, y, dx, dy):
+        xs = list(range(x, self.width - 1, 2)) if dx else list(range(x, 0, -2))
+        ys = list(range(y, self.height - 1, 2)) if dy else list(range(y, 0, -2))
+        return [(xc, yc) for yc in ys for xc in xs]
+
+    def backtrack(self, world, x, y, dx, dy):
         self.cart[y][x] = Cell("Empty")
         stack = []
         stack.append((x, y))
@@ -46,8 +186,8 @@ class Grid:
             else:
                 (xc, yc) = stack.pop()
                 def valid_neighbour(xn,yn):
-                    inBoundsX = 0 <= 2 * xn - xc < 81
-                    inBoundsY = 0 <= 2 * yn - yc < 81
+                    inBoundsX = 0 <= 2 * xn - xc < self.width
+                    inBoundsY = 0 <= 2 * yn - yc < self.height
                     dirX = xn == xc or ((xn > x) == dx)
                     dirY = yn == yc or ((yn > y) == dy)
                     if not (inBoundsX and inBoundsY and dirX and dirY): return False
@@ -66,48 +206,151 @@ class Grid:
                     self.cart[yn][xn] = Cell("Empty")
                     self.cart[2 * yn - yc][2 * xn - xc] = Cell("Empty")
 
-def generate_grid(world):
-    grid = Grid()
+def cell_neighbours(c):
+    (x, y) = c
+    return [(x + 2, y), (x, y + 2), (x - 2, y), (x, y - 2)]
 
-    for (xs, ys) in [(41, 41), (39, 41), (41, 39), (39, 39)]:
-        grid.carve(world, xs, ys, xs == 41, ys == 41)
-        grid.cart[ys][xs] = Cell("Player",pl=int(xs == 41) + 2 * int(ys == 41))
+# the passages of a spanning tree over cells (every other tile, so neighbours are 2 apart).
+# the backtracker isn't here because it carves the grid directly, to keep old seeds stable
+def maze_passages(world, algorithm, cells, start):
+    cellset = set(cells)
+    passages = []
+    if algorithm == "prim":
+        visited = {start}
+        frontier = [n for n in cell_neighbours(start) if n in cellset]
+        while len(frontier) > 0:
+            f = frontier.pop(world.random.randrange(len(frontier)))
+            links = [n for n in cell_neighbours(f) if n in visited]
+            passages.append((world.random.choice(links), f))
+            visited.add(f)
+            for n in cell_neighbours(f):
+                if n in cellset and n not in visited and n not in frontier:
+                    frontier.append(n)
+    elif algorithm == "kruskal":
+        edges = [((x, y), n) for (x, y) in cells for n in [(x + 2, y), (x, y + 2)] if n in cellset]
+        world.random.shuffle(edges)
+        parent = dict()
+        def find(c):
+            while c in parent:
+                c = parent[c]
+            return c
+        for (a, b) in edges:
+            (ra, rb) = (find(a), find(b))
+            if ra != rb:
+                parent[ra] = rb
+                passages.append((a, b))
+    elif algorithm == "wilson":
+        in_maze = {start}
+        order = list(cells)
+        world.random.shuffle(order)
+        for c in order:
+            # random walk until the maze is hit, remembering only the last exit from each cell
+            nxt = dict()
+            current = c
+            while current not in in_maze:
+                n = world.random.choice([n for n in cell_neighbours(current) if n in cellset])
+                nxt[current] = n
+                current = n
+            current = c
+            while current not in in_maze:
+                in_maze.add(current)
+                passages.append((current, nxt[current]))
+                current = nxt[current]
+    elif algorithm == "growing_tree":
+        # picks the newest cell half of the time (like the backtracker) and a random one otherwise (like prim)
+        visited = {start}
+        active = [start]
+        while len(active) > 0:
+            i = len(active) - 1 if world.random.random() < 0.5 else world.random.randrange(len(active))
+            options = [n for n in cell_neighbours(active[i]) if n in cellset and n not in visited]
+            if len(options) == 0:
+                active.pop(i)
+            else:
+                n = world.random.choice(options)
+                visited.add(n)
+                passages.append((active[i], n))
+                active.append(n)
+    return passages
+
+# the heaviest run of keys through logic, where weight(d, k) is what needing key d before key k adds.
+# logic has to be consistent, or this never finishes
+def longest_chain(logic, weight):
+    memo = dict()
+    def chain(k):
+        if k not in memo:
+            memo[k] = max([chain(d) + weight(d, k) for d in logic.get(k, [])], default=0)
+        return memo[k]
+    return max([chain(k) for k in logic], default=0)
+
+# how well a placement meets the objective, higher being harder.
+# path_length is left to the client's --objective, as it needs the solver
+def placement_score(grid, objective, keymap, logic):
+    (cx, cy) = grid.centre()
+    def quadrant(k):
+        (x, y) = keymap[k]
+        return int(x > cx) + 2 * int(y > cy)
+    if objective == "chain_depth":
+        return longest_chain(logic, lambda d, k: 1)
+    elif objective == "robot_switches":
+        return longest_chain(logic, lambda d, k: int(quadrant(d) != quadrant(k)))
+    elif objective == "balanced":
+        # the easiest quadrant sets the score, so every robot gets its share of doors
+        sums = [0, 0, 0, 0]
+        for k, v in logic.items():
+            sums[quadrant(k)] += len(v)
+        return min(sums)
+    return sum(map(len,logic.values()))
 
-    placement = (None, 0)
+def generate_grid(world, width=81, height=81, keys=26, algorithm="backtracker", braid=0, difficulty="normal", objective=None):
+    grid = Grid(width, height)
+
+    (cx, cy) = grid.centre()
+    starts = grid.starts()
+    for n in reversed(range(4)):
+        (xs, ys) = starts[n]
+        grid.carve(world, xs, ys, xs > cx, ys > cy, algorithm)
+        grid.cart[ys][xs] = Cell("Player",pl=n)
+    if braid > 0:
+        grid.braid(world, braid)
+
+    if objective is None:
+        objective = "chain_depth" if difficulty == "hard" else "requirements"
+    # (score, sum of requirements), so ties on the objective go to the placement with more requirements,
+    # or fewer on easy
+    placement = (None, (0, 0))
     i = 0
 
-    nodes = sorted(filter(lambda x: x not in [(41, 41), (39, 41), (41, 39), (39, 39)],grid.tree.keys()))
+    nodes = sorted(filter(lambda x: x not in starts,grid.tree.keys()))
+    dominators = grid.dominators(starts)
     while i < 100 or placement[0] is None:
-        iseq = iter(world.random.sample(nodes,52))
+        iseq = iter(world.random.sample(nodes,2*keys))
         keymap = dict()
         doormap = dict()
 
-        for c in 'abcdefghijklmnopqrstuvwxyz':
+        for c in KEY_ALPHABET[:keys]:
             n1 = next(iseq)
             n2 = next(iseq)
 
             current = n1
             behind = False
-            while current in grid.tree and grid.tree[current] is not None:
-                if grid.tree[current] == n2:
+            while current in dominators and dominators[current] is not None:
+                if dominators[current] == n2:
                     behind = True
                     break
                 else:
-                    current = grid.tree[current]
+                    current = dominators[current]
 
             keymap[c] = n2 if behind else n1
             doormap[n1 if behind else n2] = c
 
+        routes = grid.door_routes(starts, doormap)
         logic = dict()
         for k, v in keymap.items():
-            pathback = []
-            current = v
-            while current in grid.tree and grid.tree[current] is not None:
-                if grid.tree[current] in doormap:
-                    pathback.append(doormap[grid.tree[current]])
-                current = grid.tree[current]
-            
-            logic[k] = pathback
+            # any one route is enough, so the one with the fewest doors becomes the logic
+            own = 1 << KEY_ALPHABET.index(k)
+            usable = [r for r in routes.get(v, []) if r & own == 0]
+            doors = min(usable, key=lambda r: bin(r).count("1")) if len(usable) > 0 else 0
+            logic[k] = [d for b, d in enumerate(KEY_ALPHABET) if doors & (1 << b)]
 
         def consistent(logic):
             finished = set()
@@ -122,12 +365,13 @@ def generate_grid(world):
                 if not possible:
                     return False
 
-                if len(finished) == 26:
+                if len(finished) == len(logic):
                     return True
 
         if consistent(logic):
-            s = sum(map(len,logic.values()))
-            if s > placement[1]:
+            s = (placement_score(grid, objective, keymap, logic), sum(map(len,logic.values())))
+            better = s < placement[1] if difficulty == "easy" else s > placement[1]
+            if placement[0] is None or better:
                 placement = ((keymap, doormap, logic), s)
 
         i += 1
@@ -147,9 +391,25 @@ def prettyprint(grid):
             print(c.to_char(),end="")
         print("")
 
+def vault_size(world):
+    # the maze only lines up with the outer wall on sizes of the form 4n+1
+    width = world.options.width.value
+    width -= (width - 1) % 4
+    height = world.options.height.value
+    height -= (height - 1) % 4
+    keys = min(world.options.keys.value, max_keys(width, height))
+    return (width, height, keys)
+
 def get_slot_data(world):
-    grid, logic = generate_grid(world)
+    width, height, keys = vault_size(world)
+    algorithm = world.options.algorithm.current_key
+    braid = world.options.braid.value
+    difficulty = world.options.difficulty.current_key
+    objective = world.options.objective.current_key
+    if objective == "difficulty":
+        objective = None
+    grid, logic = generate_grid(world, width, height, keys, algorithm, braid, difficulty, objective)
     print(logic)
     prettyprint(grid)
     input("")
-    return {"logic":logic,"grid":list(map(lambda r: list(map(lambda c: c.to_char(),r)),grid.cart))}
\ No newline at end of file
+    return {"logic":logic,"width":width,"height":height,"algorithm":algorithm,"braid":braid,"grid":list(map(lambda r: list(map(lambda c: c.to_char(),r)),grid.cart))}
\ No newline at end of file
@@ -1,10 +1,10 @@
 from ..AutoWorld import World, WebWorld
-from Options import PerGameCommonOptions, FreeText
+from Options import PerGameCommonOptions, FreeText, Range, Choice
 from BaseClasses import Item, Location, Region, ItemClassification, Tutorial
 from dataclasses import dataclass
 from Utils import user_path
 from ..generic.Rules import add_rule
-from .Create import get_slot_data
+from .Create import get_slot_data, vault_size, KEY_ALPHABET
 
 class AOCManyWorldsItem(Item):
     game: str = "Advent of Code 2019 Day 18 Part 2"
@@ -12,9 +12,78 @@ class AOCManyWorldsItem(Item):
 class AOCManyWorldsLocation(Location):
     game: str = "Advent of Code 2019 Day 18 Part 2"
 
+class Width(Range):
+    """Width of the vault. Rounded down to one more than a multiple of 4."""
+    display_name = "Width"
+    range_start = 13
+    range_end = 201
+    default = 81
+
+class Height(Range):
+    """Height of the vault. Rounded down to one more than a multiple of 4."""
+    display_name = "Height"
+    range_start = 13
+    range_end = 201
+    default = 81
+
+class Keys(Range):
+    """Number of keys (and doors) in the vault. Past 26, keys use greek and cyrillic letters.
+    Small vaults can't fit as many keys, and will have fewer."""
+    display_name = "Keys"
+    range_start = 1
+    range_end = len(KEY_ALPHABET)
+    default = 26
+
+class Algorithm(Choice):
+    """Maze generation algorithm used to carve the vault.
+    The backtracker makes long winding corridors, while prim and kruskal make lots of short dead ends."""
+    display_name = "Algorithm"
+    option_backtracker = 0
+    option_prim = 1
+    option_kruskal = 2
+    option_wilson = 3
+    option_growing_tree = 4
+    default = 0
+
+class Braid(Range):
+    """Percentage of dead ends knocked through into loops, so more than one route can lead to a key.
+    0 keeps the vault a perfect maze, like the original puzzle."""
+    display_name = "Braid"
+    range_start = 0
+    range_end = 100
+    default = 0
+
+class Difficulty(Choice):
+    """Out of 100 random key and door placements, easy keeps the one with the fewest requirements,
+    normal the one with the most, and hard the one with the longest chain of keys needed for one another."""
+    display_name = "Difficulty"
+    option_easy = 0
+    option_normal = 1
+    option_hard = 2
+    default = 1
+
+class Objective(Choice):
+    """What the placements are scored on, if not the difficulty's own choice.
+    requirements counts every door needed, chain_depth the longest chain of keys needed for one another,
+    robot_switches how often that chain moves to another robot, and balanced the doors in the least demanding quadrant."""
+    display_name = "Objective"
+    option_difficulty = 0
+    option_requirements = 1
+    option_chain_depth = 2
+    option_robot_switches = 3
+    option_balanced = 4
+    default = 0
+
 @dataclass
 class AOCManyWorldsOptions(PerGameCommonOptions):
     dummy: FreeText
+    width: Width
+    height: Height
+    keys: Keys
+    algorithm: Algorithm
+    braid: Braid
+    difficulty: Difficulty
+    objective: Objective
 
 class AOCManyWorldsWeb(WebWorld):
     tutorials = [
@@ -41,18 +110,21 @@ class AOCManyWorldsWorld(World):
     topology_present = False
     web = WebWorld()
 
-    item_id_to_name = {ord(c):c for c in "abcdefghijklmnopqrstuvwxyz"}
-    location_id_to_name = {ord(c):c for c in "abcdefghijklmnopqrstuvwxyz"}
-    item_name_to_id = {c:ord(c) for c in "abcdefghijklmnopqrstuvwxyz"}
-    location_name_to_id = {c:ord(c) for c in "abcdefghijklmnopqrstuvwxyz"}
+    item_id_to_name = {ord(c):c for c in KEY_ALPHABET}
+    location_id_to_name = {ord(c):c for c in KEY_ALPHABET}
+    item_name_to_id = {c:ord(c) for c in KEY_ALPHABET}
+    location_name_to_id = {c:ord(c) for c in KEY_ALPHABET}
 
     options_dataclass = AOCManyWorldsOptions
 
     slot_data = None
 
+    def key_names(self):
+        return KEY_ALPHABET[:vault_size(self)[2]]
+
     def create_regions(self):
         menu = Region("Menu",self.player,self.multiworld)
-        menu.locations += [AOCManyWorldsLocation(self.player, c, self.location_name_to_id[c], menu) for c in "abcdefghijklmnopqrstuvwxyz"]
+        menu.locations += [AOCManyWorldsLocation(self.player, c, self.location_name_to_id[c], menu) for c in self.key_names()]
         self.multiworld.regions.append(menu)
 
     def set_rules(self):
@@ -61,13 +133,13 @@ class AOCManyWorldsWorld(World):
         logic = self.slot_data["logic"]
         for k, ks in logic.items():
             add_rule(self.multiworld.get_location(k,self.player), lambda state: all(state.has(c,self.player) for c in ks))
-        self.multiworld.completion_condition[self.player] = lambda state: all(state.has(c,self.player) for c in "abcdefghijklmnopqrstuvwxyz")
+        self.multiworld.completion_condition[self.player] = lambda state: all(state.has(c,self.player) for c in self.key_names())
 
     def create_item(self, name):
         return AOCManyWorldsItem(name, ItemClassification.progression, self.item_name_to_id[name], self.player)
     
     def create_items(self):
-        keys = [self.create_item(name) for name in "abcdefghijklmnopqrstuvwxyz"]
+        keys = [self.create_item(name) for name in self.key_names()]
         print(len(keys))
         self.multiworld.itempool += keys
 
@@ -2,38 +2,51 @@ use archipelago_rs::{
     client::{ArchipelagoClient, ArchipelagoError},
     protocol::{
         ClientMessage, ClientStatus, Connected, DataStorageOperation, Get, ItemsHandlingFlags,
-        NetworkItem, NetworkItemFlags, Retrieved, RichMessageColor, RichMessagePart, RichPrint,
-        ServerMessage, Set,
+        NetworkItem, NetworkItemFlags, RichMessageColor, RichMessagePart, RichPrint, ServerMessage,
+        Set,
     },
 };
 use crossterm::{
     self,
     cursor::{Hide, MoveTo, Show},
-    event::{Event, EventStream, KeyCode, KeyEvent, read},
+    event::{Event, EventStream, KeyCode, KeyEvent, poll, read},
     execute, queue,
     style::{
         Color::{self, Rgb},
         Print, ResetColor, SetBackgroundColor, SetForegroundColor,
     },
     terminal::{
-        Clear, ClearType, DisableLineWrap, EnterAlternateScreen, disable_raw_mode, enable_raw_mode,
-        size,
+        Clear, ClearType, DisableLineWrap, EnterAlternateScreen, LeaveAlternateScreen,
+        disable_raw_mode, enable_raw_mode, size,
     },
 };
 use futures_util::{FutureExt, StreamExt};
-use rand::{Rng, SeedableRng, rng, seq::IndexedRandom};
+use rand::{
+    Rng, SeedableRng, rng,
+    seq::{IndexedRandom, SliceRandom},
+};
 use rand_chacha::ChaCha8Rng;
 use serde_json::{Value, json};
 use std::{
-    collections::{HashMap, HashSet, VecDeque},
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
     env::args,
     fmt::Debug,
     io::{Write, stdout},
     iter::repeat_n,
+    path::{Path, PathBuf},
     process::exit,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, channel},
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    select,
+    time::{error::Elapsed, sleep, timeout},
 };
-use tokio::{select, time::error::Elapsed};
 
 #[tokio::main]
 async fn main() -> Result<(), StrError> {
@@ -42,10 +55,12 @@ async fn main() -> Result<(), StrError> {
     let _ = rc.next();
     while let Some(opt) = rc.next() {
         match &opt[..] {
-            "--help" | "--singleplayer" => {
+            "--help" | "--singleplayer" | "--solve" | "--resume" | "--fog" => {
                 argmap.insert(opt, String::new());
             }
-            "--url" | "--password" | "--slot" | "--seed" => match rc.next() {
+            "--url" | "--password" | "--slot" | "--seed" | "--export-input" | "--map"
+            | "--width" | "--height" | "--keys" | "--algorithm" | "--braid" | "--difficulty"
+            | "--objective" | "--multiworld-seed" | "--player" | "--load" => match rc.next() {
                 Some(s) => {
                     argmap.insert(opt, s);
                 }
@@ -65,30 +80,74 @@ async fn main() -> Result<(), StrError> {
     if argmap.contains_key("--help") {
         println!(concat!(
             "Join a multiworld:\n",
-            "aoc-manyworlds-archi --url <url> [--password <password>] --slot <slot>\n\n",
+            "aoc-manyworlds-archi --url <url> [--password <password>] --slot <slot> [--fog]\n\n",
             "Play without joining a multiworld:\n",
-            "aoc-manyworlds-archi --singleplayer [--seed <seed>]\n\n",
+            "aoc-manyworlds-archi --singleplayer [--seed <seed> | --map <file> | --multiworld-seed <seed> [--player <n>]]\n",
+            "    [--width <w>] [--height <h>] [--keys <n>]\n",
+            "    [--algorithm backtracker|prim|kruskal|wilson|growing-tree] [--braid <percent>]\n",
+            "    [--difficulty easy|normal|hard] [--objective requirements|chain-depth|robot-switches|path-length|balanced]\n",
+            "    [--resume] [--fog]\n\n",
+            "Carry on a saved singleplayer run:\n",
+            "aoc-manyworlds-archi --load <file> [--fog]\n\n",
+            "Print the optimal solution of a singleplayer vault:\n",
+            "aoc-manyworlds-archi --solve [--seed <seed> | --map <file>]\n\n",
+            "Write a vault as a plain puzzle input file:\n",
+            "aoc-manyworlds-archi --export-input <path> [--seed <seed> | --map <file>]\n",
+            "aoc-manyworlds-archi --export-input <path> --url <url> [--password <password>] --slot <slot>\n\n",
             "Print help\n",
             "aoc-manyworlds-archi --help"
         ));
     } else if argmap.contains_key("--singleplayer") {
-        match argmap.get("--seed") {
-            Some(n) => {
-                if let Ok(seed) = n.parse() {
-                    start_singleplayer(seed)?
-                } else {
-                    return Err(StrError {
-                        msg: "seed must be a number!".to_string(),
-                    });
-                }
+        let (grid, name) = vault_from_args(&argmap)?;
+        let path = save_path(&name);
+        let resume = if argmap.contains_key("--resume") {
+            let run = load_run(&path)?;
+            if run.vault != grid.to_input() {
+                return Err(StrError {
+                    msg: format!(
+                        "the run saved in {} is of a different vault, are the options the same?",
+                        path.display()
+                    ),
+                });
             }
-            None => start_singleplayer(rng().random())?,
+            Some(run)
+        } else if path.exists() {
+            return Err(StrError {
+                msg: format!(
+                    "there's already a run of this vault saved in {}. carry on with --resume, or delete it to start over",
+                    path.display()
+                ),
+            });
+        } else {
+            None
         };
+        start_singleplayer(grid, name, path, resume, argmap.contains_key("--fog"))?;
+    } else if let Some(path) = argmap.get("--load") {
+        let run = load_run(Path::new(path))?;
+        let grid = Grid::from_input(&run.vault)?;
+        start_singleplayer(
+            grid,
+            run.name.clone(),
+            PathBuf::from(path),
+            Some(run),
+            argmap.contains_key("--fog"),
+        )?;
+    } else if let Some(path) = argmap.get("--export-input") {
+        export_input(path, &argmap).await?;
+    } else if argmap.contains_key("--solve") {
+        let (grid, name) = vault_from_args(&argmap)?;
+        print_solution(grid, name)?;
     } else if let Some(url) = argmap.get("--url")
         && let Some(slot) = argmap.get("--slot")
     {
         let password = argmap.get("--password");
-        start_multiplayer(url.clone(), slot.clone(), password.map(|x| x.as_str())).await?;
+        start_multiplayer(
+            url.clone(),
+            slot.clone(),
+            password.map(|x| x.as_str()),
+            argmap.contains_key("--fog"),
+        )
+        .await?;
     } else {
         return Err(StrError {
             msg: "Invalid syntax. Try \"aoc-manyworlds-archi --help\".".to_string(),
@@ -97,6 +156,99 @@ async fn main() -> Result<(), StrError> {
     Ok(())
 }
 
+fn vault_from_args(argmap: &HashMap<String, String>) -> Result<(Grid, String), StrError> {
+    if let Some(path) = argmap.get("--map") {
+        let input = std::fs::read_to_string(path)?;
+        Ok((Grid::from_input(&input)?, path.clone()))
+    } else if let Some(s) = argmap.get("--multiworld-seed") {
+        let seed: u128 = s.parse().map_err(|_| StrError {
+            msg: "multiworld seed must be a number!".to_string(),
+        })?;
+        let player: usize = match argmap.get("--player") {
+            Some(p) => p.parse().ok().filter(|p| *p > 0).ok_or(StrError {
+                msg: "player must be a slot number, starting from 1".to_string(),
+            })?,
+            None => 1,
+        };
+        let options = options_from_args(argmap)?;
+        Ok((
+            Grid::generate_multiworld(seed, player, &options)?.0,
+            format!(
+                "multiworld seed {seed}, player {player}, {}",
+                options.describe()
+            ),
+        ))
+    } else {
+        let seed = seed_from_args(argmap)?;
+        let options = options_from_args(argmap)?;
+        Ok((
+            Grid::generate_grid(seed, &options)?.0,
+            format!("seed {seed}, {}", options.describe()),
+        ))
+    }
+}
+
+fn seed_from_args(argmap: &HashMap<String, String>) -> Result<u64, StrError> {
+    match argmap.get("--seed") {
+        Some(n) => n.parse().map_err(|_| StrError {
+            msg: "seed must be a number!".to_string(),
+        }),
+        None => Ok(rng().random()),
+    }
+}
+
+fn options_from_args(argmap: &HashMap<String, String>) -> Result<GridOptions, StrError> {
+    let mut options = GridOptions::default();
+    if let Some(w) = argmap.get("--width") {
+        options.width = w.parse().map_err(|_| StrError {
+            msg: "width must be a number!".to_string(),
+        })?;
+    }
+    if let Some(h) = argmap.get("--height") {
+        options.height = h.parse().map_err(|_| StrError {
+            msg: "height must be a number!".to_string(),
+        })?;
+    }
+    if let Some(k) = argmap.get("--keys") {
+        options.keys = k.parse().map_err(|_| StrError {
+            msg: "key count must be a number!".to_string(),
+        })?;
+    }
+    if let Some(a) = argmap.get("--algorithm") {
+        options.algorithm = MazeAlgorithm::from_name(a).ok_or(StrError {
+            msg: format!("unknown maze algorithm {a}"),
+        })?;
+    }
+    if let Some(b) = argmap.get("--braid") {
+        options.braid = b.parse().map_err(|_| StrError {
+            msg: "braid must be a percentage from 0 to 100".to_string(),
+        })?;
+    }
+    if let Some(d) = argmap.get("--difficulty") {
+        options.difficulty = Difficulty::from_name(d).ok_or(StrError {
+            msg: format!("unknown difficulty {d}"),
+        })?;
+    }
+    if let Some(o) = argmap.get("--objective") {
+        options.objective = Some(Objective::from_name(o).ok_or(StrError {
+            msg: format!("unknown placement objective {o}"),
+        })?);
+    }
+    if argmap.contains_key("--multiworld-seed") {
+        // the apworld sizes its vault down to fit rather than refusing options
+        options.width -= (options.width - 1).rem_euclid(4);
+        options.height -= (options.height - 1).rem_euclid(4);
+        options.keys = options.keys.min(options.max_keys());
+        if options.objective() == Objective::PathLength {
+            return Err(StrError {
+                msg: "the apworld can't place keys by path length".to_string(),
+            });
+        }
+    }
+    options.validate()?;
+    Ok(options)
+}
+
 struct StrError {
     msg: String,
 }
@@ -127,16 +279,68 @@ impl From<Elapsed> for StrError {
     }
 }
 
-fn start_singleplayer(seed: u64) -> Result<(), StrError> {
-    let (grid, _logic) = Grid::generate_grid(seed);
+// stands for the terminal being in raw mode on the alternate screen, and puts it back when dropped,
+// so that an error ending the game can be read
+struct RawTerminal;
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), ResetColor, Show, LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
+}
+
+// how long a robot travelling on its own takes over each step
+const TRAVEL_STEP: Duration = Duration::from_millis(40);
+
+fn start_singleplayer(
+    grid: Grid,
+    name: String,
+    save: PathBuf,
+    resume: Option<SavedRun>,
+    fog: bool,
+) -> Result<(), StrError> {
+    let vault = grid.to_input();
+    let players = grid.robots().try_into().map_err(|_| StrError {
+        msg: "vault must have exactly 4 robots".to_string(),
+    })?;
     let mut initstate = GridState {
+        all_keys: grid.keys(),
         grid,
         player: 0,
-        players: [(39, 39), (41, 39), (39, 41), (41, 41)],
+        players,
         keys: HashSet::new(),
         msgs: repeat_n(RichPrint::message(String::new()), 5).collect(),
         finished: false,
+        stats: Stats::default(),
+        singleplayer: true,
+        history: Vec::new(),
+        undone: Vec::new(),
+        goto: None,
+        travel: VecDeque::new(),
+        overlay: false,
+        view: View::Single,
+        explored: fog.then(HashSet::new),
+        banner: None,
     };
+    let mut elapsed = Duration::ZERO;
+    if let Some(run) = resume {
+        // a run started in the fog stays in it
+        if run.explored.is_some() {
+            initstate.explored = run.explored;
+        }
+        for key in run.keys {
+            initstate.remove_cell(Cell::Key(key));
+            initstate.use_key(&key);
+        }
+        initstate.place_players(run.players);
+        initstate.player = run.player % 4;
+        initstate.stats = run.stats;
+        elapsed = run.elapsed;
+    }
+    initstate.look();
+    let started = Instant::now();
+    let original = Grid::from_input(&vault)?;
 
     enable_raw_mode()?;
     execute!(
@@ -146,88 +350,372 @@ fn start_singleplayer(seed: u64) -> Result<(), StrError> {
         Clear(ClearType::All),
         Hide,
     )?;
+    let _terminal = RawTerminal;
+    let mut screen = Screen::default();
+    let mut report = None;
     loop {
-        initstate.draw(&"???".to_string())?;
-        match read()? {
-            Event::Key(k) => {
-                if let Some(k) = initstate.process_key(k)? {
+        if initstate.complete() && !initstate.finished {
+            initstate.finished = true;
+            report = Some(report_later(&initstate, original.clone(), false));
+            // a finished run isn't kept, so the vault can be played again from the start
+            if save.exists() {
+                std::fs::remove_file(&save)?;
+            }
+        }
+        if let Some(lines) = report.as_ref().and_then(|r| r.lines.try_recv().ok()) {
+            for line in lines {
+                initstate.msgs.pop_front();
+                initstate.msgs.push_back(RichPrint::message(line));
+            }
+            report = None;
+        }
+        initstate.draw(&mut screen, &"???".to_string())?;
+        // a travelling robot takes its next step whenever no key is pressed in time,
+        // and the report's looked for as often while it's being worked out
+        let event = if (initstate.travel.is_empty() && report.is_none()) || poll(TRAVEL_STEP)? {
+            Some(read()?)
+        } else {
+            None
+        };
+        match event {
+            None => {
+                let moves = initstate.history.len();
+                if let Some(k) = initstate.travel_step()? {
                     initstate.use_key(&k);
                 }
+                if initstate.history.len() > moves {
+                    initstate.undone.clear();
+                }
+            }
+            Some(Event::Key(k)) => {
+                if k.code == KeyCode::Char('q') && !initstate.finished {
+                    save_run(
+                        &save,
+                        &vault,
+                        &name,
+                        &initstate,
+                        elapsed + started.elapsed(),
+                    )?;
+                }
+                let typing = initstate.goto.is_some() || !initstate.travel.is_empty();
+                if k.is_press() && k.code == KeyCode::Char('u') && !typing {
+                    initstate.undo(&original);
+                    if !initstate.finished {
+                        report = None;
+                    }
+                } else if k.is_press() && k.code == KeyCode::Char('r') && !typing {
+                    initstate.redo()?;
+                } else {
+                    let moves = initstate.history.len();
+                    if let Some(k) = initstate.process_key(k)? {
+                        initstate.use_key(&k);
+                    }
+                    if initstate.history.len() > moves {
+                        initstate.undone.clear();
+                    }
+                }
             }
+            // the draw at the top of the loop lays everything out again for the new size
+            Some(Event::Resize(..)) => continue,
             _ => continue,
         }
 
-        if initstate.keys.len() == 26 {
-            initstate.finished = true;
+        if !initstate.finished {
+            save_run(
+                &save,
+                &vault,
+                &name,
+                &initstate,
+                elapsed + started.elapsed(),
+            )?;
         }
     }
 }
 
-async fn start_multiplayer(
-    url: String,
-    slot: String,
-    password: Option<&str>,
+// the report on how the run went, being worked out on another thread. dropping it stops the
+// thread, as it could otherwise take a while over a report that's no longer wanted
+struct PendingReport {
+    lines: Receiver<Vec<String>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for PendingReport {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// starts on the report for a finished run, so the game doesn't freeze while it's worked out
+fn report_later(state: &GridState, original: Grid, multiworld: bool) -> PendingReport {
+    let (send, lines) = channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stats = state.stats.clone();
+    let stopped = stop.clone();
+    std::thread::spawn(move || send.send(stats.report(&original, multiworld, &stopped)));
+    PendingReport { lines, stop }
+}
+
+// multiworld keys waiting for the server to confirm them, kept next to the saved runs
+fn journal_path(seed: &str, slot: &str) -> PathBuf {
+    save_path(&format!("{seed} {slot} pending"))
+}
+
+fn save_journal(path: &Path, keys: &[char]) -> Result<(), StrError> {
+    if keys.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    let keys: Vec<Value> = keys.iter().map(|k| Value::String(k.to_string())).collect();
+    std::fs::write(path, Value::Array(keys).to_string())?;
+    Ok(())
+}
+
+fn load_journal(path: &Path) -> Result<Vec<char>, StrError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text = std::fs::read_to_string(path)?;
+    let keys = serde_json::from_str(&text)
+        .ok()
+        .and_then(|v| keystring_from_storage(&v).ok())
+        .ok_or(StrError {
+            msg: format!("{} isn't a list of pending keys", path.display()),
+        })?;
+    Ok(keys.into_iter().collect())
+}
+
+// a singleplayer run as saved to disk
+struct SavedRun {
+    vault: String, // the puzzle input before any keys were picked up
+    name: String,
+    players: [(i16, i16); 4],
+    player: usize,
+    keys: HashSet<char>,
+    stats: Stats,
+    elapsed: Duration,
+    explored: Option<HashSet<(i16, i16)>>, // when played in the fog
+}
+
+// runs are saved in the working directory, named after the vault they're of
+fn save_path(name: &str) -> PathBuf {
+    let words: Vec<&str> = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    PathBuf::from(format!("aoc-manyworlds-{}.json", words.join("-")))
+}
+
+fn save_run(
+    path: &Path,
+    vault: &str,
+    name: &str,
+    state: &GridState,
+    elapsed: Duration,
 ) -> Result<(), StrError> {
-    let mut con: ArchipelagoClient<Value> = match ArchipelagoClient::new(&url).await {
-        Ok(c) => c,
+    let mut keys: Vec<String> = state.keys.iter().map(|k| k.to_string()).collect();
+    keys.sort();
+    let run = json!({
+        "vault": vault,
+        "name": name,
+        "players": seriaize_players(state.players),
+        "player": state.player,
+        "keys": keys,
+        "stats": serialize_stats(&state.stats),
+        "elapsed": elapsed.as_secs(),
+        "explored": state.explored.as_ref().map(|e| serialize_explored(e, (state.grid.cart[0].len(), state.grid.cart.len()))),
+    });
+    std::fs::write(path, run.to_string())?;
+    Ok(())
+}
+
+fn load_run(path: &Path) -> Result<SavedRun, StrError> {
+    let unreadable = |what: &str| StrError {
+        msg: format!("{} isn't a saved run: {what}", path.display()),
+    };
+    let text = std::fs::read_to_string(path).map_err(|e| StrError {
+        msg: format!("couldn't read a saved run from {}: {e}", path.display()),
+    })?;
+    let run: Value = serde_json::from_str(&text).map_err(|_| unreadable("not JSON"))?;
+    let number = |field: &str| {
+        run.get(field)
+            .and_then(|n| n.as_u64())
+            .ok_or(unreadable(&format!("no {field}")))
+    };
+    let vault = run
+        .get("vault")
+        .and_then(|v| v.as_str())
+        .ok_or(unreadable("no vault"))?
+        .to_string();
+    let size = (
+        vault.lines().next().map_or(0, |l| l.chars().count()),
+        vault.lines().count(),
+    );
+    let players = players_from_storage(run.get("players").ok_or(unreadable("no players"))?)?;
+    if players
+        .iter()
+        .any(|(x, y)| *x < 0 || *y < 0 || *x as usize >= size.0 || *y as usize >= size.1)
+    {
+        return Err(unreadable("a robot is outside the vault"));
+    }
+    Ok(SavedRun {
+        name: run
+            .get("name")
+            .and_then(|v| v.as_str())
+            .unwrap_or("saved run")
+            .to_string(),
+        players,
+        player: number("player")? as usize,
+        keys: keystring_from_storage(run.get("keys").ok_or(unreadable("no keys"))?)?,
+        stats: stats_from_storage(run.get("stats").ok_or(unreadable("no stats"))?)?,
+        elapsed: Duration::from_secs(number("elapsed")?),
+        explored: match run.get("explored") {
+            Some(Value::Null) | None => None,
+            Some(e) => Some(explored_from_storage(e, size)?),
+        },
+        vault,
+    })
+}
+
+fn print_solution(grid: Grid, name: String) -> Result<(), StrError> {
+    let solution = grid.solve(SOLVE_LIMIT)?.ok_or(StrError {
+        msg: format!("vault from {name} has no solution"),
+    })?;
+    println!("vault: {name}");
+    println!("minimum steps: {}", solution.steps);
+    println!(
+        "key order:   {}",
+        solution.order.iter().map(|(_, c)| c).collect::<String>()
+    );
+    println!(
+        "robot order: {}",
+        solution
+            .order
+            .iter()
+            .map(|(n, _)| n.to_string())
+            .collect::<String>()
+    );
+    Ok(())
+}
+
+async fn connect_to_slot(
+    url: &str,
+    slot: &str,
+    password: Option<&str>,
+) -> Result<(ArchipelagoClient<Value>, Connected<Value>), StrError> {
+    match open_slot(url, slot, password).await {
+        Ok(c) => Ok(c),
         Err(ArchipelagoError::NetworkError(e)) => {
             descriptive_network_error(e);
         }
-        Err(e) => {
-            return Err(StrError::from(e));
-        }
-    };
-    con = ArchipelagoClient::with_data_package(&url, Some(con.room_info().games.clone())).await?;
+        Err(e) => Err(StrError::from(e)),
+    }
+}
+
+async fn open_slot(
+    url: &str,
+    slot: &str,
+    password: Option<&str>,
+) -> Result<(ArchipelagoClient<Value>, Connected<Value>), ArchipelagoError> {
+    let con: ArchipelagoClient<Value> = ArchipelagoClient::new(url).await?;
+    let mut con =
+        ArchipelagoClient::with_data_package(url, Some(con.room_info().games.clone())).await?;
 
     let con_package: Connected<Value> = con
         .connect(
             "Advent of Code 2019 Day 18 Part 2",
-            &slot,
+            slot,
             password,
             ItemsHandlingFlags::all(),
             vec!["AP".to_string()],
         )
         .await?;
+    Ok((con, con_package))
+}
+
+async fn export_input(path: &str, argmap: &HashMap<String, String>) -> Result<(), StrError> {
+    let grid = if let Some(url) = argmap.get("--url")
+        && let Some(slot) = argmap.get("--slot")
+    {
+        let password = argmap.get("--password");
+        let (_con, con_package) = connect_to_slot(url, slot, password.map(|x| x.as_str())).await?;
+        grid_from_connected(&con_package)?
+    } else {
+        let (grid, name) = vault_from_args(argmap)?;
+        println!("vault: {name}");
+        grid
+    };
+    std::fs::write(path, grid.to_input())?;
+    println!("wrote puzzle input to {path}");
+    Ok(())
+}
+
+// how long to wait before reconnecting, doubled for each try that has failed, up to 32 times as long
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+// how long a try at reconnecting gets before it counts as failed
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// how long a line about a key sent from another world is held back, in case it's one of a release
+// or collect, which is summed up in one line instead
+const BATCH_WAIT: Duration = Duration::from_millis(500);
+
+async fn start_multiplayer(
+    url: String,
+    slot: String,
+    password: Option<&str>,
+    fog: bool,
+) -> Result<(), StrError> {
+    let (mut con, mut con_package) = connect_to_slot(&url, &slot, password).await?;
     let data_package = con
         .data_package()
         .ok_or(StrError {
             msg: "could not retrieve data package".to_string(),
         })?
         .clone();
-    let _logic: HashMap<char, Vec<char>> = logic_from_connected(&con_package)?; //store here for sanity
+    let logic: HashMap<char, Vec<char>> = logic_from_connected(&con_package)?;
     let grid = grid_from_connected(&con_package)?;
+    grid.validate(&logic)?;
+    let players = grid.robots().try_into().map_err(|_| StrError {
+        msg: "vault must have exactly 4 robots".to_string(),
+    })?;
 
-    con.send(ClientMessage::Set(Set {
-        key: slot.clone() + "_players",
-        default: seriaize_players([(39, 39), (41, 39), (39, 41), (41, 41)]),
-        want_reply: false,
-        operations: vec![DataStorageOperation::Remove(json!([-1, -1]))],
-    }))
-    .await?;
-    con.send(ClientMessage::Set(Set {
-        key: slot.clone() + "_keystring",
-        default: Value::Array(Vec::new()),
-        want_reply: false,
-        operations: vec![DataStorageOperation::Remove(json!('?'))],
-    }))
-    .await?;
-    con.send(ClientMessage::Get(Get {
-        keys: vec![slot.clone() + "_keystring"],
-    }))
-    .await?;
-    con.send(ClientMessage::Get(Get {
-        keys: vec![slot.clone() + "_players"],
-    }))
-    .await?;
+    // keys picked up last time that the server never confirmed
+    let journal = journal_path(&con.room_info().seed_name, &slot);
+    let mut pending = load_journal(&journal)?;
+    // the ones it did confirm before the client stopped don't need sending again
+    pending.retain(|k| !con_package.checked_locations.contains(&(*k as i64)));
+    save_journal(&journal, &pending)?;
+    if !pending.is_empty() {
+        con.location_checks(pending.iter().map(|k| *k as i64).collect())
+            .await?;
+    }
+    let size = (grid.cart[0].len(), grid.cart.len());
+    request_state(&mut con, &slot, players, fog, size).await?;
 
     let mut initstate = GridState {
+        all_keys: grid.keys(),
         grid,
         player: 0,
-        players: [(39, 39), (41, 39), (39, 41), (41, 41)],
+        players,
         keys: HashSet::new(),
         msgs: repeat_n(RichPrint::message(String::new()), 5).collect(),
         finished: false,
+        stats: Stats::default(),
+        singleplayer: false,
+        history: Vec::new(),
+        undone: Vec::new(),
+        goto: None,
+        travel: VecDeque::new(),
+        overlay: false,
+        view: View::Single,
+        explored: fog.then(HashSet::new),
+        banner: None,
     };
+    initstate.remove_checked(&con_package.checked_locations);
+    for k in &pending {
+        initstate.remove_cell(Cell::Key(*k));
+    }
+    initstate.look();
 
     enable_raw_mode()?;
     execute!(
@@ -238,78 +726,401 @@ async fn start_multiplayer(
         DisableLineWrap,
         Hide,
     )?;
+    let _terminal = RawTerminal;
 
     let mut reader = EventStream::new();
-    let mut keysretrieved = false;
     let mut playersretrieved = false;
+    let mut statsretrieved = false;
 
+    let mut con = Some(con);
+    // item ids in the order the server gave them, so its length is the index expected next
+    let mut received: Vec<i64> = Vec::new();
+    // keys picked up that haven't been sent yet, out of the ones pending confirmation
+    let mut queued: Vec<char> = Vec::new();
+    let mut goal_sent = false;
+    let mut attempts = 0;
+    let mut retry_at = Instant::now();
+    let mut connecting = None;
+    // lines about keys sent from other worlds, waiting to see whether a batch of keys brings them
+    let mut held: VecDeque<(Instant, i64, RichPrint)> = VecDeque::new();
+    // keys a batch brought before the server got round to the lines about them
+    let mut batched: Vec<(Instant, i64)> = Vec::new();
+    let mut report = None;
+    let mut screen = Screen::default();
     loop {
-        if keysretrieved && playersretrieved {
-            initstate.draw(&slot)?;
+        if let Some(lines) = report
+            .as_ref()
+            .and_then(|r: &PendingReport| r.lines.try_recv().ok())
+        {
+            for line in lines {
+                initstate.msgs.pop_front();
+                initstate.msgs.push_back(RichPrint::message(line));
+            }
+            report = None;
+        }
+        if playersretrieved {
+            initstate.draw(&mut screen, &slot)?;
         }
 
         let event = reader.next().fuse();
 
+        let mut moved = None;
+        let mut lost = false;
+        let mut resync = false;
+        let explored = initstate.explored.as_ref().map_or(0, |e| e.len());
         select! {
             maybe_event = event => {
-                if let Some(Ok(Event::Key(key))) = maybe_event {
-                    if let Some(k) = initstate.process_key(key)? {
-                        con.location_checks(vec![k as i64]).await?;
-                        con.send(ClientMessage::Set(Set {
-                            key: slot.clone()+"_keystring",
-                            default: Value::Array(vec![Value::String(k.to_string())]),
-                            want_reply: false,
-                            operations: vec![DataStorageOperation::Update(json!([k.to_string()]))]
-                        })).await?;
+                match maybe_event {
+                    Some(Ok(Event::Key(key))) => moved = Some(initstate.process_key(key)?),
+                    // the draw at the top of the loop lays everything out again for the new size
+                    Some(Ok(Event::Resize(..))) => continue,
+                    _ => (),
+                }
+            },
+            _ = sleep(TRAVEL_STEP), if !initstate.travel.is_empty() => {
+                moved = Some(initstate.travel_step()?);
+            },
+            // looking for the report again at the top of the loop
+            _ = sleep(TRAVEL_STEP), if report.is_some() => {},
+            _ = sleep(held.front().map_or(Duration::ZERO, |(at, ..)| at.saturating_duration_since(Instant::now()))), if !held.is_empty() => {
+                let now = Instant::now();
+                let due = held.iter().take_while(|(at, ..)| *at <= now).count();
+                for (_, _, msg) in held.drain(..due) {
+                    initstate.msgs.pop_front();
+                    initstate.msgs.push_back(msg);
+                }
+            },
+            // the attempt goes on alongside the game, so it can still be played, or quit
+            _ = sleep(retry_at.saturating_duration_since(Instant::now())), if con.is_none() && connecting.is_none() => {
+                connecting = Some(timeout(CONNECT_TIMEOUT, open_slot(&url, &slot, password)).boxed_local());
+            },
+            attempt = async {
+                match &mut connecting {
+                    Some(c) => c.await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                connecting = None;
+                // connecting again sends Connect, and the server answers with every item from index 0
+                match attempt {
+                    Ok(Ok((c, package))) => {
+                        con = Some(c);
+                        con_package = package;
+                        attempts = 0;
+                        initstate.banner = None;
+                        let checked = &con_package.checked_locations;
+                        initstate.remove_checked(checked);
+                        pending.retain(|k| !checked.contains(&(*k as i64)));
+                        save_journal(&journal, &pending)?;
+                        queued = pending.clone();
+                        resync = true;
+                    }
+                    _ => {
+                        attempts += 1;
+                        lost = true;
                     }
-                    con.send(ClientMessage::Set(Set {
-                        key: slot.clone()+"_players",
-                        default: seriaize_players(initstate.players),
-                        want_reply: false,
-                        operations: vec![DataStorageOperation::Replace(seriaize_players(initstate.players))]
-                    })).await?;
                 }
             },
-            server_msg = con.recv() => {
+            server_msg = async {
+                match &mut con {
+                    Some(c) => c.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => {
                 match server_msg {
                     Ok(Some(ServerMessage::ReceivedItems(items))) => {
-                        let is = items.items;
-                        initstate.add_items(is)?;
+                        // index 0 is the whole list, as after connecting or a Sync
+                        if items.index == 0 {
+                            received.clear();
+                        }
+                        let in_order = items.index == received.len() as i64;
+                        if in_order {
+                            received.extend(items.items.iter().map(|i| i.item));
+                        }
+                        // keys can be added whatever the order, but a gap or an overlap means
+                        // the list has to be fetched again to know where it's up to
+                        if let Some(c) = &mut con {
+                            let sent = if in_order {
+                                c.send(ClientMessage::Set(Set {
+                                    key: slot.clone() + "_received",
+                                    default: json!([received.len()]),
+                                    want_reply: false,
+                                    operations: vec![DataStorageOperation::Replace(json!([received.len()]))],
+                                }))
+                                .await
+                            } else {
+                                c.sync().await
+                            };
+                            if sent.is_err() {
+                                lost = true;
+                            }
+                        }
+                        let new = initstate.add_items(items.items);
+                        // several keys at once from other worlds, past the start of the list, is most
+                        // likely a release or collect, which gets one line rather than one for each key
+                        let collected: Vec<&NetworkItem> = new
+                            .iter()
+                            .filter(|i| i.player != con_package.slot)
+                            .filter(|i| initstate.key_from_id(i.item).is_some())
+                            .collect();
+                        if in_order && items.index > 0 && collected.len() > 1 {
+                            let deadline = Instant::now() + BATCH_WAIT;
+                            for i in &collected {
+                                match held.iter().position(|(_, id, _)| *id == i.item) {
+                                    Some(h) => {
+                                        held.remove(h);
+                                    }
+                                    None => batched.push((deadline, i.item)),
+                                }
+                            }
+                            initstate.msgs.pop_front();
+                            initstate.msgs.push_back(RichPrint::message(format!(
+                                "{} keys received: {}",
+                                collected.len(),
+                                collected
+                                    .iter()
+                                    .filter_map(|i| initstate.key_from_id(i.item))
+                                    .collect::<String>()
+                            )));
+                        }
                     },
                     Ok(Some(ServerMessage::Print(msg))) => {
                         initstate.msgs.pop_front();
                         initstate.msgs.push_back(RichPrint::message(msg.text));
                     },
                     Ok(Some(ServerMessage::RichPrint(mut msg))) => {
-                        initstate.msgs.pop_front();
                         msg.add_names(&con_package, &data_package);
-                        initstate.msgs.push_back(msg);
+                        let now = Instant::now();
+                        batched.retain(|(at, _)| *at > now);
+                        match sent_key(&msg, con_package.slot).filter(|id| initstate.key_from_id(*id).is_some()) {
+                            Some(id) => match batched.iter().position(|(_, k)| *k == id) {
+                                // already summed up along with the rest of its batch
+                                Some(b) => {
+                                    batched.remove(b);
+                                }
+                                None => held.push_back((now + BATCH_WAIT, id, msg)),
+                            },
+                            None => {
+                                initstate.msgs.pop_front();
+                                initstate.msgs.push_back(msg);
+                            }
+                        }
                     },
                     Ok(Some(ServerMessage::Retrieved(items))) => {
-                        if let Ok(keys) = keystring_from_storage(&value_from_singleton(&items)?) {
-                            for key in keys {initstate.remove_cell(Cell::Key(key))};
-                            keysretrieved = true;
+                        if let Some(players) = items.keys.get(slot.clone()+"_players")
+                            && let Ok(players) = players_from_storage(players)
+                        {
+                            initstate.place_players(players);
+                            playersretrieved = true;
                         }
-                        if let Ok(players) = players_from_storage(&value_from_singleton(&items)?) {
-                            for i in 0..4 {
-                                initstate.remove_cell(Cell::Player(i));
+                        if let Some(stats) = items.keys.get(slot.clone()+"_stats") {
+                            // keys that came in before the stats did count as arriving now
+                            let mut stats = stats_from_storage(stats)?;
+                            for k in &initstate.keys {
+                                if !stats.arrivals.iter().any(|(a, _)| a == k) {
+                                    stats.arrivals.push((*k, stats.route.len()));
+                                }
                             }
-                            initstate.players = players;
-                            for ((x,y),n) in initstate.players.iter().zip(0..4) {
-                                initstate.grid.cart[*y as usize][*x as usize] = Cell::Player(n);
+                            initstate.stats = stats;
+                            statsretrieved = true;
+                        }
+                        // how far through the list the slot had got when it was last played
+                        if let Some(Value::Array(count)) = items.keys.get(slot.clone()+"_received")
+                            && let Some(n) = count.first().and_then(|n| n.as_u64())
+                        {
+                            let away: String = received
+                                .iter()
+                                .skip(n as usize)
+                                .filter_map(|i| initstate.key_from_id(*i))
+                                .collect();
+                            if !away.is_empty() {
+                                initstate.msgs.pop_front();
+                                initstate.msgs.push_back(RichPrint::message(format!(
+                                    "keys received while you were away: {away}"
+                                )));
                             }
-                            playersretrieved = true;
+                        }
+                        if let Some(explored) = items.keys.get(slot.clone()+"_explored")
+                            && let Some(known) = &mut initstate.explored
+                        {
+                            known.extend(explored_from_storage(explored, size)?);
+                        }
+                    }
+                    // locations checked by anyone on the slot, this client included, or by a collect
+                    Ok(Some(ServerMessage::RoomUpdate(update))) => {
+                        if let Some(checked) = update.checked_locations {
+                            initstate.remove_checked(&checked);
+                            pending.retain(|k| !checked.contains(&(*k as i64)));
+                            save_journal(&journal, &pending)?;
                         }
                     }
+                    Ok(None) | Err(ArchipelagoError::ConnectionClosed | ArchipelagoError::NetworkError(_)) => {
+                        lost = true;
+                    }
                     _ => {}
                 }
             }
         };
-        if initstate.keys.len() == 26 {
-            con.status_update(ClientStatus::ClientGoal).await?;
+        if let Some(Some(k)) = moved {
+            pending.push(k);
+            save_journal(&journal, &pending)?;
+            queued.push(k);
+        }
+        if statsretrieved && initstate.complete() && !initstate.finished {
             initstate.finished = true;
+            report = Some(report_later(
+                &initstate,
+                grid_from_connected(&con_package)?,
+                true,
+            ));
+        }
+        if let Some(c) = &mut con {
+            let sent = if resync && !playersretrieved {
+                // nothing came back before the connection went, so ask again rather than
+                // overwrite what's stored with a vault that hasn't been filled in yet
+                request_state(c, &slot, initstate.players, fog, size)
+                    .await
+                    .map(|()| false)
+            } else if resync || moved.is_some() || (initstate.finished && !goal_sent) {
+                let grown = resync
+                    || initstate
+                        .explored
+                        .as_ref()
+                        .is_some_and(|e| e.len() > explored);
+                let goal = initstate.finished && !goal_sent;
+                send_progress(c, &slot, &initstate, &queued, grown, goal, size)
+                    .await
+                    .map(|()| true)
+            } else {
+                Ok(false)
+            };
+            match sent {
+                Ok(true) => {
+                    queued.clear();
+                    goal_sent = initstate.finished;
+                }
+                Ok(false) => {}
+                Err(_) => lost = true,
+            }
         }
+        if lost {
+            // the next try waits twice as long as the last, up to about half a minute
+            con = None;
+            retry_at = Instant::now() + RECONNECT_BACKOFF * 2u32.pow(attempts.min(5));
+            initstate.banner = Some(format!(
+                "connection lost, reconnecting… (attempt {})",
+                attempts + 1
+            ));
+        }
+    }
+}
+
+// asks for everything stored about the slot, setting defaults first so there's always an answer
+async fn request_state(
+    con: &mut ArchipelagoClient<Value>,
+    slot: &str,
+    players: [(i16, i16); 4],
+    fog: bool,
+    size: (usize, usize),
+) -> Result<(), ArchipelagoError> {
+    con.send(ClientMessage::Set(Set {
+        key: slot.to_string() + "_players",
+        default: seriaize_players(players),
+        want_reply: false,
+        operations: vec![DataStorageOperation::Remove(json!([-1, -1]))],
+    }))
+    .await?;
+    con.send(ClientMessage::Get(Get {
+        keys: vec![slot.to_string() + "_players"],
+    }))
+    .await?;
+    con.send(ClientMessage::Set(Set {
+        key: slot.to_string() + "_stats",
+        default: serialize_stats(&Stats::default()),
+        want_reply: false,
+        operations: vec![DataStorageOperation::Remove(json!('?'))],
+    }))
+    .await?;
+    con.send(ClientMessage::Get(Get {
+        keys: vec![slot.to_string() + "_stats"],
+    }))
+    .await?;
+    con.send(ClientMessage::Set(Set {
+        key: slot.to_string() + "_received",
+        default: json!([0]),
+        want_reply: false,
+        operations: vec![DataStorageOperation::Remove(json!(-1))],
+    }))
+    .await?;
+    con.send(ClientMessage::Get(Get {
+        keys: vec![slot.to_string() + "_received"],
+    }))
+    .await?;
+    if fog {
+        con.send(ClientMessage::Set(Set {
+            key: slot.to_string() + "_explored",
+            default: serialize_explored(&HashSet::new(), size),
+            want_reply: false,
+            operations: vec![DataStorageOperation::Remove(json!(-1))],
+        }))
+        .await?;
+        con.send(ClientMessage::Get(Get {
+            keys: vec![slot.to_string() + "_explored"],
+        }))
+        .await?;
+    }
+    Ok(())
+}
+
+// tells the server about the queued keys, where the robots are and the stats,
+// the explored cells when there are new ones, and the goal once it's reached
+async fn send_progress(
+    con: &mut ArchipelagoClient<Value>,
+    slot: &str,
+    state: &GridState,
+    queued: &[char],
+    explored: bool,
+    goal: bool,
+    size: (usize, usize),
+) -> Result<(), ArchipelagoError> {
+    if !queued.is_empty() {
+        con.location_checks(queued.iter().map(|k| *k as i64).collect())
+            .await?;
+    }
+    con.send(ClientMessage::Set(Set {
+        key: slot.to_string() + "_players",
+        default: seriaize_players(state.players),
+        want_reply: false,
+        operations: vec![DataStorageOperation::Replace(seriaize_players(
+            state.players,
+        ))],
+    }))
+    .await?;
+    con.send(ClientMessage::Set(Set {
+        key: slot.to_string() + "_stats",
+        default: serialize_stats(&state.stats),
+        want_reply: false,
+        operations: vec![DataStorageOperation::Replace(serialize_stats(&state.stats))],
+    }))
+    .await?;
+    // only when there's something new, as it's the biggest thing stored
+    if let Some(known) = &state.explored
+        && explored
+    {
+        con.send(ClientMessage::Set(Set {
+            key: slot.to_string() + "_explored",
+            default: serialize_explored(known, size),
+            want_reply: false,
+            operations: vec![DataStorageOperation::Replace(serialize_explored(
+                known, size,
+            ))],
+        }))
+        .await?;
+    }
+    if goal {
+        con.status_update(ClientStatus::ClientGoal).await?;
     }
+    Ok(())
 }
 
 fn logic_from_connected(
@@ -408,10 +1219,32 @@ fn grid_from_connected(package: &Connected<Value>) -> Result<Grid, ArchipelagoEr
                         received: "empty string",
                     },
                 )?)?)
+            } else {
+                return Err(ArchipelagoError::IllegalResponse {
+                    expected: "string",
+                    received: "non-string JSON type",
+                });
             }
         }
         cart.push(cellrow);
     }
+
+    // older worlds don't send their dimensions, in which case the grid is taken as is
+    let dims = [
+        ("width", cart.first().map_or(0, |r| r.len())),
+        ("height", cart.len()),
+    ];
+    for (field, len) in dims {
+        if let Some(n) = data.get(field).and_then(|n| n.as_u64())
+            && n as usize != len
+        {
+            return Err(ArchipelagoError::IllegalResponse {
+                expected: "grid matching the width and height in slot data",
+                received: "grid of a different size",
+            });
+        }
+    }
+
     Ok(Grid {
         cart,
         tree: HashMap::new(),
@@ -498,33 +1331,128 @@ fn seriaize_players([(x1, y1), (x2, y2), (x3, y3), (x4, y4)]: [(i16, i16); 4]) -
     json!([[x1, y1], [x2, y2], [x3, y3], [x4, y4]])
 }
 
-fn value_from_singleton(storage: &Retrieved) -> Result<Value, ArchipelagoError> {
-    let obj = if let Value::Object(o) = &storage.keys {
-        o
-    } else {
-        return Err(ArchipelagoError::IllegalResponse {
-            expected: "object",
-            received: "non-object JSON type",
-        });
+// a bitmap of the vault, 32 cells to a number
+fn serialize_explored(explored: &HashSet<(i16, i16)>, (width, height): (usize, usize)) -> Value {
+    let mut words = vec![0u32; (width * height).div_ceil(32)];
+    for (x, y) in explored {
+        let i = *y as usize * width + *x as usize;
+        words[i / 32] |= 1 << (i % 32);
+    }
+    json!(words)
+}
+
+fn explored_from_storage(
+    storage: &Value,
+    (width, height): (usize, usize),
+) -> Result<HashSet<(i16, i16)>, ArchipelagoError> {
+    let malformed = || ArchipelagoError::IllegalResponse {
+        expected: "explored cells",
+        received: "something else",
     };
-    obj.values()
-        .next()
-        .ok_or(ArchipelagoError::IllegalResponse {
-            expected: "nonempty object",
-            received: "empty object",
+    let words: Vec<u64> = storage
+        .as_array()
+        .ok_or_else(malformed)?
+        .iter()
+        .map(|w| w.as_u64().ok_or_else(malformed))
+        .collect::<Result<_, _>>()?;
+    Ok((0..width * height)
+        .filter(|i| words.get(i / 32).is_some_and(|w| w >> (i % 32) & 1 == 1))
+        .map(|i| ((i % width) as i16, (i / width) as i16))
+        .collect())
+}
+
+fn serialize_stats(stats: &Stats) -> Value {
+    // an array rather than an object, so it can be created with the same no-op removal as the others
+    let route: Vec<Value> = stats
+        .route
+        .iter()
+        .map(|p| {
+            json!([
+                p.robot,
+                p.key.to_string(),
+                p.steps,
+                seriaize_players(p.players)
+            ])
         })
-        .cloned()
+        .collect();
+    let arrivals: Vec<Value> = stats
+        .arrivals
+        .iter()
+        .map(|(k, n)| json!([k.to_string(), n]))
+        .collect();
+    json!([
+        stats.steps,
+        stats.keys,
+        stats.switches,
+        stats.last,
+        route,
+        arrivals
+    ])
 }
 
-fn descriptive_network_error(err: tungstenite::Error) -> ! {
-    match err {
-        tungstenite::Error::ConnectionClosed => {
-            eprintln!("connection closed");
-            exit(1)
-        }
-        tungstenite::Error::AlreadyClosed => {
-            eprintln!("already closed");
-            exit(1)
+fn stats_from_storage(storage: &Value) -> Result<Stats, ArchipelagoError> {
+    let malformed = || ArchipelagoError::IllegalResponse {
+        expected: "array of step counts, key counts, switches and last robot",
+        received: "something else",
+    };
+    let counts = |v: &Value| -> Option<[usize; 4]> {
+        let mut counts = [0; 4];
+        for (c, n) in counts.iter_mut().zip(v.as_array()?) {
+            *c = n.as_u64()? as usize;
+        }
+        Some(counts)
+    };
+    let key = |v: &Value| v.as_str().and_then(|s| s.chars().next());
+    let pickup = |v: &Value| -> Option<Pickup> {
+        match v.as_array().map(|v| &v[..]) {
+            Some([robot, k, steps, players]) => Some(Pickup {
+                robot: robot.as_u64()? as usize % 4,
+                key: key(k)?,
+                steps: steps.as_u64()? as usize,
+                players: players_from_storage(players).ok()?,
+            }),
+            _ => None,
+        }
+    };
+    let arrival = |v: &Value| -> Option<(char, usize)> {
+        match v.as_array().map(|v| &v[..]) {
+            Some([k, n]) => Some((key(k)?, n.as_u64()? as usize)),
+            _ => None,
+        }
+    };
+    let list = |v: Option<&Value>| v.and_then(|v| v.as_array()).cloned().unwrap_or_default();
+
+    let Some([steps, keys, switches, last, rest @ ..]) = storage.as_array().map(|v| &v[..]) else {
+        return Err(malformed());
+    };
+    Ok(Stats {
+        steps: counts(steps).ok_or_else(malformed)?,
+        keys: counts(keys).ok_or_else(malformed)?,
+        switches: switches.as_u64().ok_or_else(malformed)? as usize,
+        last: last.as_u64().map(|l| l as usize % 4),
+        // runs from before routes were kept don't have these
+        route: list(rest.first())
+            .iter()
+            .map(pickup)
+            .collect::<Option<_>>()
+            .ok_or_else(malformed)?,
+        arrivals: list(rest.get(1))
+            .iter()
+            .map(arrival)
+            .collect::<Option<_>>()
+            .ok_or_else(malformed)?,
+    })
+}
+
+fn descriptive_network_error(err: tungstenite::Error) -> ! {
+    match err {
+        tungstenite::Error::ConnectionClosed => {
+            eprintln!("connection closed");
+            exit(1)
+        }
+        tungstenite::Error::AlreadyClosed => {
+            eprintln!("already closed");
+            exit(1)
         }
         tungstenite::Error::Io(e) => {
             eprintln!("io: {e}");
@@ -569,7 +1497,19 @@ fn descriptive_network_error(err: tungstenite::Error) -> ! {
     };
 }
 
-fn rich_print(slot: &String, msg: RichPrint) -> Result<(), StrError> {
+// the item a line is about, if it's another world sending it to this slot
+fn sent_key(msg: &RichPrint, slot: i64) -> Option<i64> {
+    let elsewhere = msg
+        .data()
+        .iter()
+        .any(|part| matches!(part, RichMessagePart::LocationId { player, .. } if *player != slot));
+    msg.data().iter().find_map(|part| match part {
+        RichMessagePart::ItemId { id, player, .. } if elsewhere && *player == slot => Some(*id),
+        _ => None,
+    })
+}
+
+fn rich_print(screen: &mut Screen, y: i16, slot: &String, msg: &RichPrint) {
     fn col_from_flags(flags: &NetworkItemFlags) -> Color {
         if flags.contains(NetworkItemFlags::PROGRESSION) {
             Rgb {
@@ -594,31 +1534,26 @@ fn rich_print(slot: &String, msg: RichPrint) -> Result<(), StrError> {
         }
     }
 
+    let mut x = 0;
     for part in msg.data() {
-        match part {
+        let (text, col) = match part {
             RichMessagePart::PlayerId { id: _, name } => {
                 let name = name.clone().unwrap_or(Arc::new("???".to_string()));
-                execute!(
-                    stdout(),
-                    SetForegroundColor(if name == Arc::new(slot.clone()) {
-                        Color::Magenta
-                    } else {
-                        Color::Yellow
-                    }),
-                    Print(name),
-                )?;
+                let col = if name == Arc::new(slot.clone()) {
+                    Color::Magenta
+                } else {
+                    Color::Yellow
+                };
+                (name.to_string(), col)
             }
             RichMessagePart::PlayerName { text } => {
                 let name = text;
-                execute!(
-                    stdout(),
-                    SetForegroundColor(if name == slot {
-                        Color::Magenta
-                    } else {
-                        Color::Yellow
-                    }),
-                    Print(name),
-                )?;
+                let col = if name == slot {
+                    Color::Magenta
+                } else {
+                    Color::Yellow
+                };
+                (name.clone(), col)
             }
             RichMessagePart::ItemId {
                 id: _,
@@ -627,67 +1562,128 @@ fn rich_print(slot: &String, msg: RichPrint) -> Result<(), StrError> {
                 name,
             } => {
                 let name = name.clone().unwrap_or(Arc::new("???".to_string()));
-                execute!(
-                    stdout(),
-                    SetForegroundColor(col_from_flags(flags)),
-                    Print(name),
-                )?;
+                (name.to_string(), col_from_flags(flags))
             }
             RichMessagePart::ItemName {
                 text,
                 flags,
                 player: _,
-            } => {
-                let name = text;
-                execute!(
-                    stdout(),
-                    SetForegroundColor(col_from_flags(flags)),
-                    Print(name),
-                )?;
-            }
+            } => (text.clone(), col_from_flags(flags)),
             RichMessagePart::LocationId {
                 id: _,
                 player: _,
                 name,
             } => {
                 let name = name.clone().unwrap_or(Arc::new("???".to_string()));
-                execute!(stdout(), SetForegroundColor(Color::Green), Print(name),)?;
+                (name.to_string(), Color::Green)
             }
-            RichMessagePart::LocationName { text, player: _ } => {
-                let name = text;
-                execute!(stdout(), SetForegroundColor(Color::Green), Print(name),)?;
+            RichMessagePart::LocationName { text, player: _ } => (text.clone(), Color::Green),
+            RichMessagePart::EntranceName { text } => (text.clone(), Color::Blue),
+            RichMessagePart::Color { text, color } => {
+                let col = match color {
+                    RichMessageColor::Red => Color::Red,
+                    RichMessageColor::Green => Color::Green,
+                    RichMessageColor::Yellow => Color::Yellow,
+                    RichMessageColor::Blue => Color::Blue,
+                    RichMessageColor::Magenta => Color::Magenta,
+                    RichMessageColor::Cyan => Color::Cyan,
+                    _ => Color::White,
+                };
+                (text.clone(), col)
             }
-            RichMessagePart::EntranceName { text } => {
-                let name = text;
-                execute!(stdout(), SetForegroundColor(Color::Blue), Print(name),)?;
+            RichMessagePart::Text { text } => (text.clone(), Color::Reset),
+        };
+        x = screen.print(x, y, &text, col);
+    }
+}
+
+// what's on the terminal, and what's to go on it next. only the cells that differ get written,
+// and colours only get set when they change
+#[derive(Default)]
+struct Screen {
+    cols: i16,
+    rows: i16,
+    front: Vec<(char, Color, Color)>,
+    back: Vec<(char, Color, Color)>,
+    colours: Option<(Color, Color)>, // the terminal's, as last set
+}
+
+impl Screen {
+    // starts a frame with everything blank, and returns its size
+    fn begin(&mut self) -> std::io::Result<(i16, i16)> {
+        let (cols, rows) = size()?;
+        let (cols, rows) = (cols as i16, rows as i16);
+        let blank = (' ', Color::Reset, Rgb { r: 0, g: 0, b: 0 });
+        // in usize, as a big enough terminal has more cells than an i16 can count
+        let cells = cols.max(0) as usize * rows.max(0) as usize;
+        if (cols, rows) != (self.cols, self.rows) {
+            // nothing on the terminal can be trusted to be where it was
+            (self.cols, self.rows) = (cols, rows);
+            self.front = vec![('\0', Color::Reset, Color::Reset); cells];
+            self.colours = None;
+            execute!(
+                stdout(),
+                ResetColor,
+                SetBackgroundColor(Rgb { r: 0, g: 0, b: 0 }),
+                Clear(ClearType::All)
+            )?;
+        }
+        self.back = vec![blank; cells];
+        Ok((cols, rows))
+    }
+
+    fn put(&mut self, x: i16, y: i16, c: char, fg: Color, bg: Color) {
+        if (0..self.cols).contains(&x) && (0..self.rows).contains(&y) {
+            self.back[y as usize * self.cols as usize + x as usize] = (c, fg, bg);
+        }
+    }
+
+    // returns where the text ends
+    fn print(&mut self, x: i16, y: i16, text: &str, fg: Color) -> i16 {
+        let mut x = x;
+        for c in text.chars() {
+            self.put(x, y, c, fg, Rgb { r: 0, g: 0, b: 0 });
+            x += 1;
+        }
+        x
+    }
+
+    fn present(&mut self) -> std::io::Result<()> {
+        let mut cursor = None;
+        for i in 0..self.back.len() {
+            let (c, fg, bg) = self.back[i];
+            if self.front[i] == (c, fg, bg) {
+                continue;
             }
-            RichMessagePart::Color { text, color } => {
-                execute!(
-                    stdout(),
-                    SetForegroundColor(match color {
-                        RichMessageColor::Red => Color::Red,
-                        RichMessageColor::Green => Color::Green,
-                        RichMessageColor::Yellow => Color::Yellow,
-                        RichMessageColor::Blue => Color::Blue,
-                        RichMessageColor::Magenta => Color::Magenta,
-                        RichMessageColor::Cyan => Color::Cyan,
-                        _ => Color::White,
-                    }),
-                    Print(text),
-                )?;
-            }
-            RichMessagePart::Text { text } => {
-                execute!(
-                    stdout(),
-                    ResetColor,
-                    SetBackgroundColor(Rgb { r: 0, g: 0, b: 0 }),
-                    Print(text)
-                )?;
-            }
-        }
-    }
-    execute!(stdout(), Print(" ".repeat(2000)),)?;
-    Ok(())
+            let (x, y) = (
+                (i % self.cols as usize) as i16,
+                (i / self.cols as usize) as i16,
+            );
+            if cursor != Some((x, y)) {
+                queue!(stdout(), MoveTo(x as u16, y as u16))?;
+            }
+            if self.colours != Some((fg, bg)) {
+                if fg == Color::Reset {
+                    // resetting takes the background with it
+                    queue!(stdout(), ResetColor, SetBackgroundColor(bg))?;
+                } else {
+                    if self.colours.is_none_or(|(f, _)| f != fg) {
+                        queue!(stdout(), SetForegroundColor(fg))?;
+                    }
+                    if self.colours.is_none_or(|(_, b)| b != bg) {
+                        queue!(stdout(), SetBackgroundColor(bg))?;
+                    }
+                }
+                self.colours = Some((fg, bg));
+            }
+            queue!(stdout(), Print(c))?;
+            // the last column leaves the cursor waiting to wrap
+            cursor = (x + 1 < self.cols).then_some((x + 1, y));
+        }
+        stdout().flush()?;
+        self.front.clone_from(&self.back);
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Copy)]
@@ -705,11 +1701,19 @@ impl Cell {
             Cell::Wall => '#',
             Cell::Empty => ' ',
             Cell::Player(n) => char::from_digit(n as u32, 10).unwrap_or('?'),
-            Cell::Door(c) => c.to_ascii_uppercase(),
+            Cell::Door(c) => c.to_uppercase().next().unwrap_or(c),
             Cell::Key(c) => c,
         }
     }
 
+    fn to_input_char(self) -> char {
+        match self {
+            Cell::Empty => '.',
+            Cell::Player(_) => '@',
+            c => c.to_char(),
+        }
+    }
+
     fn to_cell(c: char) -> Result<Self, ArchipelagoError> {
         match c {
             '#' => Ok(Cell::Wall),
@@ -722,9 +1726,12 @@ impl Cell {
                             received: "non-digit",
                         })? as i16,
                     ))
-                } else if k.is_ascii_uppercase() {
-                    Ok(Cell::Door(k.to_ascii_lowercase()))
-                } else if k.is_ascii_lowercase() {
+                } else if let Some(d) = k.to_lowercase().next()
+                    && k.is_uppercase()
+                    && KEY_ALPHABET.contains(d)
+                {
+                    Ok(Cell::Door(d))
+                } else if KEY_ALPHABET.contains(k) {
                     Ok(Cell::Key(k))
                 } else {
                     Err(ArchipelagoError::IllegalResponse {
@@ -737,6 +1744,11 @@ impl Cell {
     }
 }
 
+// past the latin alphabet, keys carry on with greek and cyrillic letters
+// whose upper and lower case can't be mistaken for a latin key or door
+const KEY_ALPHABET: &str = "abcdefghijklmnopqrstuvwxyzγδθλξπσφψωбджзийцчшщъыьэюя";
+
+#[derive(Clone)]
 struct Grid {
     cart: Vec<Vec<Cell>>,
     tree: HashMap<(i16, i16), Option<(i16, i16)>>,
@@ -744,68 +1756,203 @@ struct Grid {
 type KeyMap = HashMap<char, (i16, i16)>;
 type DoorMap = HashMap<(i16, i16), char>;
 type Logic = HashMap<char, Vec<char>>;
+type Placement = (KeyMap, DoorMap, Logic);
+type Layout = (
+    Vec<String>,
+    HashMap<i16, Vec<(i16, i16)>>,
+    HashMap<char, Vec<(i16, i16)>>,
+    HashMap<char, Vec<(i16, i16)>>,
+    Vec<char>,
+); // (problems, robots, keys, doors, letters)
+type Route = (char, (i16, i16), usize, u64, u64); // (key, where it is, distance, keys passed, doors passed)
+
+struct GridOptions {
+    width: i16,
+    height: i16,
+    keys: usize,
+    algorithm: MazeAlgorithm,
+    braid: u8, // percentage of dead ends to open up into loops
+    difficulty: Difficulty,
+    objective: Option<Objective>, // None goes with the difficulty's own objective
+}
+
+impl Default for GridOptions {
+    fn default() -> Self {
+        GridOptions {
+            width: 81,
+            height: 81,
+            keys: 26,
+            algorithm: MazeAlgorithm::Backtracker,
+            braid: 0,
+            difficulty: Difficulty::Normal,
+            objective: None,
+        }
+    }
+}
+
+// the largest width or height of a vault, as in the apworld's options
+const MAX_SIZE: i16 = 201;
+
+impl GridOptions {
+    fn validate(&self) -> Result<(), StrError> {
+        // the robots start on odd coordinates either side of the centre,
+        // so the maze only lines up with the outer wall on sizes of the form 4n+1
+        // and no bigger than the apworld allows
+        for (name, n) in [("width", self.width), ("height", self.height)] {
+            if !(13..=MAX_SIZE).contains(&n) || n % 4 != 1 {
+                return Err(StrError {
+                    msg: format!(
+                        "{name} must be from 13 to {MAX_SIZE} and one more than a multiple of 4"
+                    ),
+                });
+            }
+        }
+
+        let max = self.max_keys();
+        if self.braid > 100 {
+            return Err(StrError {
+                msg: "braid must be a percentage from 0 to 100".to_string(),
+            });
+        }
+        if self.keys < 1 || self.keys > max {
+            return Err(StrError {
+                msg: format!(
+                    "a {}x{} vault can have between 1 and {max} keys",
+                    self.width, self.height
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    fn centre(&self) -> (i16, i16) {
+        (self.width / 2, self.height / 2)
+    }
+
+    fn max_keys(&self) -> usize {
+        // every cell of a quadrant's maze and the passage behind it can hold a key or a door,
+        // apart from the robot's own cell
+        let (cx, cy) = self.centre();
+        let spots = 4 * (2 * (cx.max(0) as usize / 2) * (cy.max(0) as usize / 2)).saturating_sub(2);
+        KEY_ALPHABET.chars().count().min(spots / 2)
+    }
+
+    fn objective(&self) -> Objective {
+        self.objective.unwrap_or(self.difficulty.objective())
+    }
+
+    // the size, then whichever options aren't the defaults, as they all change the vault.
+    // it names the save, so a vault with the default options keeps the name it always had
+    fn describe(&self) -> String {
+        let default = GridOptions::default();
+        let mut words = vec![format!("{}x{}", self.width, self.height)];
+        if self.keys != default.keys {
+            words.push(format!("{} keys", self.keys));
+        }
+        if self.algorithm != default.algorithm {
+            words.push(self.algorithm.name().to_string());
+        }
+        if self.braid != default.braid {
+            words.push(format!("braid {}%", self.braid));
+        }
+        if self.difficulty != default.difficulty {
+            words.push(self.difficulty.name().to_string());
+        }
+        if self.objective() != self.difficulty.objective() {
+            words.push(format!("{} objective", self.objective().name()));
+        }
+        words.join(", ")
+    }
+
+    fn starts(&self) -> [(i16, i16); 4] {
+        let (cx, cy) = self.centre();
+        [
+            (cx - 1, cy - 1),
+            (cx + 1, cy - 1),
+            (cx - 1, cy + 1),
+            (cx + 1, cy + 1),
+        ]
+    }
+}
+
+struct Solution {
+    steps: usize,
+    order: Vec<(usize, char)>, // (robot, key) in the order the keys are picked up
+}
+
+// how many states the solver may look at before it gives up. vaults with loops in them can have
+// far more orders worth trying than there's memory for, which perfect mazes don't
+const SOLVE_LIMIT: usize = 1_000_000;
+// and how many for each of the placements tried when generating a vault for the shortest path
+const SCORE_LIMIT: usize = 20_000;
+// and how many between all the runs for the report at the end of a vault
+const REPORT_LIMIT: usize = 5_000_000;
 
 impl Grid {
-    fn generate_grid(seed: u64) -> (Grid, Logic) {
-        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    fn generate_grid(seed: u64, options: &GridOptions) -> Result<(Grid, Logic), StrError> {
+        Grid::generate_with(&mut ChaCha8Rng::seed_from_u64(seed), options)
+    }
+
+    // the vault Create.py makes for the given player (counting from 1) of a multiworld generated from seed.
+    // archipelago seeds every world's random from the multiworld's, one player after another
+    fn generate_multiworld(
+        seed: u128,
+        player: usize,
+        options: &GridOptions,
+    ) -> Result<(Grid, Logic), StrError> {
+        let mut multiworld = PyRandom::new(seed);
+        let mut world = PyRandom::new(0);
+        for _ in 0..player {
+            world = PyRandom::new(multiworld.getrandbits(64));
+        }
+        Grid::generate_with(&mut world, options)
+    }
+
+    fn generate_with(
+        rng: &mut impl MazeRng,
+        options: &GridOptions,
+    ) -> Result<(Grid, Logic), StrError> {
         let mut grid: Grid = Grid {
-            cart: vec![vec![Cell::Wall; 81]; 81],
+            cart: vec![vec![Cell::Wall; options.width as usize]; options.height as usize],
             tree: HashMap::new(),
         };
 
-        for (xs, ys) in [(41, 41), (39, 41), (41, 39), (39, 39)] {
-            grid.carve(xs, ys, xs == 41, ys == 41, &mut rng);
-            grid.cart[ys as usize][xs as usize] =
-                Cell::Player((xs == 41) as i16 + 2 * (ys == 41) as i16);
+        let (cx, cy) = options.centre();
+        let starts = options.starts();
+        for (n, &(xs, ys)) in starts.iter().enumerate().rev() {
+            grid.carve(xs, ys, xs > cx, ys > cy, options.algorithm, rng);
+            grid.cart[ys as usize][xs as usize] = Cell::Player(n as i16);
+        }
+        if options.braid > 0 {
+            grid.braid(options, rng);
         }
 
-        let mut placement: (Option<(KeyMap, DoorMap, Logic)>, usize) = (None, 0);
+        // (score, sum of requirements), so ties on the objective go to the placement with more requirements,
+        // or fewer on easy.
+        // a placement too big to score only stands in until one that can be scored turns up
+        let mut placement: (Option<Placement>, Option<(usize, usize)>) = (None, None);
         let mut i = 0;
 
-        let mut nodes: Vec<&(i16, i16)> = grid
-            .tree
-            .keys()
-            .filter(|x| ![(41, 41), (39, 41), (41, 39), (39, 39)].contains(x))
-            .collect();
+        let mut nodes: Vec<&(i16, i16)> =
+            grid.tree.keys().filter(|x| !starts.contains(x)).collect();
         nodes.sort();
+        let dominators = grid.dominators(&starts);
         while i < 100 || placement.0.is_none() {
-            let mut iseq = nodes.choose_multiple(&mut rng, 52);
+            let mut iseq = rng.sample(&nodes, 2 * options.keys).into_iter();
             let mut keymap: HashMap<char, (i16, i16)> = HashMap::new();
             let mut doormap: HashMap<(i16, i16), char> = HashMap::new();
 
-            for c in 'a'..='z' {
+            for c in KEY_ALPHABET.chars().take(options.keys) {
                 let n1 = *iseq.next().unwrap(); // ⎫
-                // ⎬ unwrap justification: iseq is guaranteed to be 52 elements long, same as 2 times 'a'..='z'
+                // ⎬ unwrap justification: iseq is guaranteed to be 2 * options.keys elements long, and options.validate() makes sure there are enough nodes
                 let n2 = *iseq.next().unwrap(); // ⎭
 
-                let mut current = n1;
-                let mut behind = false;
-                while let Some(Some(n)) = grid.tree.get(current) {
-                    if n == n2 {
-                        behind = true;
-                        break;
-                    } else {
-                        current = n
-                    }
-                }
-
+                let behind = Grid::dominates(&dominators, *n2, *n1);
                 keymap.insert(c, *if behind { n2 } else { n1 });
                 doormap.insert(*if behind { n1 } else { n2 }, c);
             }
 
-            let mut logic: HashMap<char, Vec<char>> = HashMap::new();
-            for (k, v) in &keymap {
-                let mut pathback = Vec::new();
-                let mut current = v;
-                while let Some(Some(n)) = grid.tree.get(current) {
-                    match doormap.get(n) {
-                        None => {}
-                        Some(d) => pathback.push(*d),
-                    }
-                    current = n;
-                }
-                logic.insert(*k, pathback);
-            }
+            let logic = grid.door_logic(&starts, &keymap, &doormap);
 
             fn consistent(logic: &Logic) -> bool {
                 let mut finished = HashSet::new();
@@ -822,7 +1969,7 @@ impl Grid {
                     if !possible {
                         return false;
                     };
-                    if finished.len() == 26 {
+                    if finished.len() == logic.len() {
                         return true;
                     }
                 }
@@ -830,13 +1977,31 @@ impl Grid {
 
             if consistent(&logic) {
                 let sum: usize = logic.values().map(|v| v.len()).sum();
-                if sum > placement.1 {
-                    placement = (Some((keymap, doormap, logic)), sum);
+                let score = grid
+                    .score(options, &keymap, &doormap, &logic)
+                    .map(|s| (s, sum));
+                let better = match (score, placement.1) {
+                    (None, _) => false,
+                    (Some(_), None) => true,
+                    (Some(score), Some(best)) => match options.difficulty {
+                        Difficulty::Easy => score < best,
+                        Difficulty::Normal | Difficulty::Hard => score > best,
+                    },
+                };
+                if placement.0.is_none() || better {
+                    placement = (Some((keymap, doormap, logic)), score);
                 }
             }
             i += 1;
         }
 
+        // only the path length can go unscored
+        if placement.1.is_none() {
+            return Err(StrError {
+                msg: "none of the key placements tried could be scored on path length, as they would take too long to solve. try another --objective, fewer keys or less --braid"
+                    .to_string(),
+            });
+        }
         let (keymap, doormap, logic) = placement.0.unwrap(); // unwrap justification: the above loop will not terminate with placement.0 == None
         for (c, (x, y)) in keymap {
             grid.cart[y as usize][x as usize] = Cell::Key(c)
@@ -844,183 +2009,1622 @@ impl Grid {
         for ((x, y), c) in doormap {
             grid.cart[y as usize][x as usize] = Cell::Door(c)
         }
-        (grid, logic)
+        Ok((grid, logic))
     }
 
-    fn carve(&mut self, x: i16, y: i16, dx: bool, dy: bool, rng: &mut ChaCha8Rng) {
-        self.cart[y as usize][x as usize] = Cell::Empty;
-        let mut stack: Vec<(i16, i16)> = Vec::new();
-        stack.push((x, y));
-        self.tree.insert((x, y), None);
-        loop {
-            let cur = stack.pop();
-            match cur {
-                None => break,
-                Some((xc, yc)) => {
-                    let valid_neighbour = |&&(xn, yn): &&(i16, i16)| {
-                        self.cart
-                            .get((2 * yn - yc) as usize)
-                            .and_then(|v| v.get((2 * xn - xc) as usize))
-                            == Some(&Cell::Wall)
-                            && (xn == xc || ((xn > x) == dx))
-                            && (yn == yc || ((yn > y) == dy))
-                    };
-                    let candidates = [(xc + 1, yc), (xc, yc + 1), (xc - 1, yc), (xc, yc - 1)];
-                    let neighbours: Vec<(i16, i16)> =
-                        candidates.iter().filter(valid_neighbour).copied().collect();
-                    match neighbours.choose(rng) {
-                        None => continue,
-                        Some(&(xn, yn)) => {
-                            stack.push((xc, yc));
-                            stack.push((2 * xn - xc, 2 * yn - yc));
-                            self.tree.insert((xn, yn), Some((xc, yc)));
-                            self.tree.insert((2 * xn - xc, 2 * yn - yc), Some((xn, yn)));
-                            self.cart[yn as usize][xn as usize] = Cell::Empty;
-                            self.cart[(2 * yn - yc) as usize][(2 * xn - xc) as usize] = Cell::Empty;
-                        }
-                    }
+    fn door_logic(&self, starts: &[(i16, i16)], keymap: &KeyMap, doormap: &DoorMap) -> Logic {
+        let routes = self.door_routes(starts, doormap);
+        let mut logic: HashMap<char, Vec<char>> = HashMap::new();
+        for (k, (x, y)) in keymap {
+            // any one route is enough, so the one with the fewest doors becomes the logic
+            let own = KEY_ALPHABET.chars().position(|c| c == *k).unwrap_or(63);
+            let doors = routes[*y as usize][*x as usize]
+                .iter()
+                .filter(|r| *r & (1 << own) == 0)
+                .min_by_key(|r| r.count_ones())
+                .copied()
+                .unwrap_or_default();
+            let pathback = KEY_ALPHABET
+                .chars()
+                .enumerate()
+                .filter(|(b, _)| doors & (1 << b) != 0)
+                .map(|(_, d)| d)
+                .collect();
+            logic.insert(*k, pathback);
+        }
+        logic
+    }
+
+    // where the robots, keys and doors of a vault are, and what's wrong with it as a vault: it has
+    // to be rectangular, with robots 0 to 3, each key once and each door at most once. expected
+    // are any letters there should be besides the ones found
+    fn layout(&self, expected: &[char]) -> Layout {
+        let mut problems = Vec::new();
+        let width = self.cart.first().map_or(0, |r| r.len());
+        if width == 0 || self.cart.iter().any(|r| r.len() != width) {
+            problems.push("the grid isn't rectangular".to_string());
+        }
+
+        let mut robots: HashMap<i16, Vec<(i16, i16)>> = HashMap::new();
+        let mut keymap: HashMap<char, Vec<(i16, i16)>> = HashMap::new();
+        let mut doormap: HashMap<char, Vec<(i16, i16)>> = HashMap::new();
+        for (y, row) in self.cart.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                let at = (x as i16, y as i16);
+                match cell {
+                    Cell::Player(n) => robots.entry(*n).or_default().push(at),
+                    Cell::Key(c) => keymap.entry(*c).or_default().push(at),
+                    Cell::Door(c) => doormap.entry(*c).or_default().push(at),
+                    _ => {}
                 }
             }
         }
+        for n in 0..4 {
+            match robots.get(&n).map_or(0, |r| r.len()) {
+                1 => {}
+                found => problems.push(format!("robot {n} appears {found} times")),
+            }
+        }
+        let mut extra: Vec<&i16> = robots.keys().filter(|n| !(0..4).contains(*n)).collect();
+        extra.sort();
+        for n in extra {
+            problems.push(format!("there's an unexpected robot {n}"));
+        }
+
+        let mut letters: Vec<char> = keymap
+            .keys()
+            .chain(doormap.keys())
+            .chain(expected)
+            .copied()
+            .collect();
+        letters.sort();
+        letters.dedup();
+        for c in &letters {
+            let door = Cell::Door(*c).to_char();
+            match keymap.get(c).map_or(0, |k| k.len()) {
+                1 => {}
+                found => problems.push(format!("key {c} appears {found} times")),
+            }
+            // a key needn't have a door, as in the examples from the puzzle
+            match doormap.get(c).map_or(0, |d| d.len()) {
+                0 | 1 => {}
+                found => problems.push(format!("door {door} appears {found} times")),
+            }
+        }
+        (problems, robots, keymap, doormap, letters)
     }
-}
-struct GridState {
-    grid: Grid,
-    player: usize,
-    players: [(i16, i16); 4],
-    keys: HashSet<char>,
-    msgs: VecDeque<RichPrint>,
-    finished: bool,
-}
 
-impl GridState {
-    fn draw(&self, slot: &String) -> Result<(), StrError> {
-        let offset = 27;
-        let (xp, yp) = self.players[self.player];
-        let (cols, rows) = size()?;
-        let width = cols as i16 - offset - 1;
-        let height = rows as i16 - 6;
-        let (xs, ys) = (offset + width / 2, height / 2);
-
-        let mut keystring = String::new();
-        for c in 'a'..='z' {
-            keystring.push(if self.keys.contains(&c) { c } else { ' ' })
-        }
-
-        execute!(
-            stdout(),
-            ResetColor,
-            SetBackgroundColor(Rgb { r: 0, g: 0, b: 0 }),
-            MoveTo(0, 0),
-            Print("[wasd]/arrows to move"),
-            MoveTo(0, 1),
-            Print("[c] to change player"),
-            MoveTo(0, 2),
-            Print("[q] to quit"),
-            MoveTo(0, 3),
-            Print("keys collected:"),
-            MoveTo(0, 4),
-            SetForegroundColor(if self.finished {
-                Rgb {
-                    r: 235,
-                    g: 255,
-                    b: 108,
-                }
-            } else {
-                Rgb {
-                    r: 255,
-                    g: 255,
-                    b: 255,
-                }
-            }),
-            Print(keystring),
-            ResetColor,
-            SetBackgroundColor(Rgb { r: 0, g: 0, b: 0 }),
-            MoveTo(0, height as u16),
-        )?;
-        rich_print(slot, self.msgs[0].clone())?;
-        execute!(stdout(), MoveTo(0, height as u16 + 1))?;
-        rich_print(slot, self.msgs[1].clone())?;
-        execute!(stdout(), MoveTo(0, height as u16 + 2))?;
-        rich_print(slot, self.msgs[2].clone())?;
-        execute!(stdout(), MoveTo(0, height as u16 + 3))?;
-        rich_print(slot, self.msgs[3].clone())?;
-        execute!(stdout(), MoveTo(0, height as u16 + 4))?;
-        rich_print(slot, self.msgs[4].clone())?;
-        execute!(
-            stdout(),
-            MoveTo(0, height as u16 + 5),
-            SetForegroundColor(if self.finished {
-                Rgb {
-                    r: 235,
-                    g: 255,
-                    b: 108,
-                }
-            } else {
-                Rgb {
-                    r: 255,
-                    g: 255,
-                    b: 255,
-                }
-            }),
-            Print(if self.finished {
-                "You have found all of your keys!"
-            } else {
-                ""
-            })
-        )?;
+    // checks a vault from slot data can be played, and that its logic is what the vault itself gives
+    fn validate(&self, logic: &Logic) -> Result<(), StrError> {
+        let expected: Vec<char> = logic.keys().copied().collect();
+        let (mut problems, robots, keymap, doormap, letters) = self.layout(&expected);
+        for c in &letters {
+            if !doormap.contains_key(c) {
+                problems.push(format!("door {} appears 0 times", Cell::Door(*c).to_char()));
+            }
+            if !logic.contains_key(c) {
+                problems.push(format!("key {c} is missing from the logic"));
+            }
+        }
 
-        for y in 0..height {
-            for x in offset..offset + width {
-                let distfactor: f64 = (((x - xs) as f64).powf(2.0)
-                    + ((2 * y - 2 * ys) as f64).powf(2.0))
-                .clamp(1.0, 255.0);
-                let k: f64 = 3.9;
-                let col_from = |n: f64| Rgb {
-                    r: (k * n / distfactor) as u8,
-                    g: (k * n / distfactor) as u8,
-                    b: (k * n / distfactor) as u8,
+        // the logic can only be worked out again once the vault itself makes sense
+        if problems.is_empty() {
+            let starts: Vec<(i16, i16)> = (0..4).map(|n| robots[&n][0]).collect();
+            let keymap: KeyMap = keymap.into_iter().map(|(c, k)| (c, k[0])).collect();
+            let doormap: DoorMap = doormap.into_iter().map(|(c, d)| (d[0], c)).collect();
+            let expected = self.door_logic(&starts, &keymap, &doormap);
+            for c in &letters {
+                let sorted = |v: &Vec<char>| {
+                    let mut v = v.clone();
+                    v.sort();
+                    v.into_iter().collect::<String>()
                 };
+                let (given, needed) = (sorted(&logic[c]), sorted(&expected[c]));
+                if given != needed {
+                    problems.push(format!(
+                        "the logic says key {c} needs {given:?}, but in the vault it needs {needed:?}"
+                    ));
+                }
+            }
+        }
 
-                let cell = if (y - ys + yp) < 0 || (x - xs + xp) < 0 {
-                    &Cell::Wall
-                } else {
-                    self.grid
-                        .cart
-                        .get((y - ys + yp) as usize)
-                        .and_then(|v| v.get((x - xs + xp) as usize))
-                        .unwrap_or(&Cell::Wall)
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(StrError {
+                msg: format!(
+                    "the slot data doesn't describe a playable vault:\n{}",
+                    problems.join("\n")
+                ),
+            })
+        }
+    }
+
+    // how well a placement meets options.objective, higher being harder,
+    // or None when working out the path length would take too long
+    fn score(
+        &self,
+        options: &GridOptions,
+        keymap: &KeyMap,
+        doormap: &DoorMap,
+        logic: &Logic,
+    ) -> Option<usize> {
+        let (cx, cy) = options.centre();
+        let quadrant = |k: &char| {
+            keymap
+                .get(k)
+                .map(|(x, y)| (*x > cx) as usize + 2 * (*y > cy) as usize)
+        };
+        match options.objective() {
+            Objective::Requirements => Some(logic.values().map(|v| v.len()).sum()),
+            Objective::ChainDepth => Some(longest_chain(logic, |_, _| 1)),
+            Objective::RobotSwitches => Some(longest_chain(logic, |d, k| {
+                (quadrant(d) != quadrant(k)) as usize
+            })),
+            Objective::PathLength => {
+                let mut placed = Grid {
+                    cart: self.cart.clone(),
+                    tree: HashMap::new(),
                 };
-                let col = match cell {
-                    Cell::Player(n) => {
-                        if self.player == *n as usize {
-                            col_from(255.0)
-                        } else {
-                            col_from(128.0)
-                        }
+                for (c, (x, y)) in keymap {
+                    placed.cart[*y as usize][*x as usize] = Cell::Key(*c)
+                }
+                for ((x, y), c) in doormap {
+                    placed.cart[*y as usize][*x as usize] = Cell::Door(*c)
+                }
+                placed
+                    .solve(SCORE_LIMIT)
+                    .ok()
+                    .map(|s| s.map_or(0, |s| s.steps))
+            }
+            Objective::Balanced => {
+                // the easiest quadrant sets the score, so every robot gets its share of doors
+                let mut sums = [0; 4];
+                for (k, v) in logic {
+                    if let Some(q) = quadrant(k) {
+                        sums[q] += v.len();
                     }
-                    Cell::Wall | Cell::Empty => col_from(64.0),
-                    Cell::Key(_) | Cell::Door(_) => col_from(255.0),
-                };
-                queue!(
-                    stdout(),
-                    MoveTo(x as u16, y as u16),
-                    SetForegroundColor(col),
-                    Print(cell.to_char())
-                )?;
+                }
+                sums.into_iter().min()
             }
         }
-        stdout().flush()?;
-        Ok(())
     }
 
-    fn move_player(&mut self, dx: i16, dy: i16) -> std::io::Result<Option<char>> {
-        let (xp, yp) = self.players[self.player];
-        if xp + dx < 0 || yp + dy < 0 {
-            return Ok(None);
+    // the spot every route from the robots to each open spot has to go through last.
+    // in a perfect maze that's just the parent in the tree, but loops give other ways round
+    fn dominators(&self, starts: &[(i16, i16)]) -> HashMap<(i16, i16), Option<(i16, i16)>> {
+        let open = |(x, y): (i16, i16)| {
+            x >= 0
+                && y >= 0
+                && self.cart.get(y as usize).and_then(|v| v.get(x as usize)) != Some(&Cell::Wall)
+                && self
+                    .cart
+                    .get(y as usize)
+                    .and_then(|v| v.get(x as usize))
+                    .is_some()
+        };
+        let neighbours = |(x, y): (i16, i16)| {
+            [(x + 1, y), (x, y + 1), (x - 1, y), (x, y - 1)].map(|n| (n, open(n)))
+        };
+
+        let mut postorder: Vec<(i16, i16)> = Vec::new();
+        let mut seen: HashSet<(i16, i16)> = HashSet::new();
+        for start in starts {
+            seen.insert(*start);
+            let mut stack = vec![(*start, 0)];
+            while let Some((c, i)) = stack.pop() {
+                if i == 4 {
+                    postorder.push(c);
+                    continue;
+                }
+                stack.push((c, i + 1));
+                let (n, o) = neighbours(c)[i];
+                if o && seen.insert(n) {
+                    stack.push((n, 0));
+                }
+            }
         }
-        let target = self
-            .grid
+        let number: HashMap<(i16, i16), usize> =
+            postorder.iter().zip(0..).map(|(c, i)| (*c, i)).collect();
+
+        // cooper, harvey and kennedy's iterative algorithm
+        let mut idom: HashMap<(i16, i16), (i16, i16)> = starts.iter().map(|s| (*s, *s)).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for c in postorder.iter().rev().filter(|c| !starts.contains(c)) {
+                let mut new: Option<(i16, i16)> = None;
+                for (p, o) in neighbours(*c) {
+                    if !o || !idom.contains_key(&p) {
+                        continue;
+                    }
+                    new = Some(match new {
+                        None => p,
+                        Some(mut a) => {
+                            let mut b = p;
+                            while a != b {
+                                while number[&a] < number[&b] {
+                                    a = idom[&a];
+                                }
+                                while number[&b] < number[&a] {
+                                    b = idom[&b];
+                                }
+                            }
+                            a
+                        }
+                    });
+                }
+                if let Some(n) = new
+                    && idom.get(c) != Some(&n)
+                {
+                    idom.insert(*c, n);
+                    changed = true;
+                }
+            }
+        }
+        idom.into_iter()
+            .map(|(c, d)| (c, if c == d { None } else { Some(d) }))
+            .collect()
+    }
+
+    // whether every route from the robots to spot goes through by
+    fn dominates(
+        dominators: &HashMap<(i16, i16), Option<(i16, i16)>>,
+        by: (i16, i16),
+        spot: (i16, i16),
+    ) -> bool {
+        let mut current = spot;
+        while let Some(Some(n)) = dominators.get(&current) {
+            if *n == by {
+                return true;
+            }
+            current = *n;
+        }
+        false
+    }
+
+    // the doors (as bits in KEY_ALPHABET order) on every route from the robots to each spot,
+    // leaving out routes whose doors are a superset of another route's
+    fn door_routes(&self, starts: &[(i16, i16)], doormap: &DoorMap) -> Vec<Vec<Vec<u64>>> {
+        let bits: HashMap<(i16, i16), u64> = doormap
+            .iter()
+            .map(|(p, d)| {
+                (
+                    *p,
+                    1 << KEY_ALPHABET.chars().position(|c| c == *d).unwrap_or(63),
+                )
+            })
+            .collect();
+        let mut routes: Vec<Vec<Vec<u64>>> = self
+            .cart
+            .iter()
+            .map(|v| vec![Vec::new(); v.len()])
+            .collect();
+        let mut queue = VecDeque::new();
+        for (x, y) in starts {
+            routes[*y as usize][*x as usize].push(0);
+            queue.push_back(((*x, *y), 0));
+        }
+        while let Some(((xc, yc), doors)) = queue.pop_front() {
+            for (xn, yn) in [(xc + 1, yc), (xc, yc + 1), (xc - 1, yc), (xc, yc - 1)] {
+                if xn < 0
+                    || yn < 0
+                    || self
+                        .cart
+                        .get(yn as usize)
+                        .and_then(|v| v.get(xn as usize))
+                        .is_none_or(|c| *c == Cell::Wall)
+                {
+                    continue;
+                }
+                let passed = doors | bits.get(&(xn, yn)).unwrap_or(&0);
+                let here = &mut routes[yn as usize][xn as usize];
+                if here.iter().any(|r| r & passed == *r) {
+                    continue;
+                }
+                here.retain(|r| r & passed != passed);
+                here.push(passed);
+                queue.push_back(((xn, yn), passed));
+            }
+        }
+        routes
+    }
+
+    fn from_input(input: &str) -> Result<Grid, StrError> {
+        let mut cart = Vec::new();
+        let mut robots = 0;
+        for (y, line) in input.lines().enumerate() {
+            // a space is open floor, so only lines with nothing else on them are skipped
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut row = Vec::new();
+            for (x, c) in line.chars().enumerate() {
+                row.push(if c == '@' {
+                    robots += 1;
+                    Cell::Player(robots - 1)
+                } else {
+                    Cell::to_cell(c).map_err(|_| StrError {
+                        msg: format!("unexpected {c:?} at line {}, column {}", y + 1, x + 1),
+                    })?
+                });
+            }
+            cart.push(row);
+        }
+
+        let mut grid = Grid {
+            cart,
+            tree: HashMap::new(),
+        };
+        match robots {
+            1 => grid.split_entrance()?,
+            4 => {}
+            n => {
+                return Err(StrError {
+                    msg: format!("expected 1 or 4 entrances, found {n}"),
+                });
+            }
+        }
+        let (problems, ..) = grid.layout(&[]);
+        if !problems.is_empty() {
+            return Err(StrError {
+                msg: format!("the map isn't a playable vault:\n{}", problems.join("\n")),
+            });
+        }
+        Ok(grid)
+    }
+
+    // turns a part 1 map into a part 2 map by walling off the single entrance
+    fn split_entrance(&mut self) -> Result<(), StrError> {
+        let (x, y) = self.robots()[0];
+        let mut replacement = [
+            Cell::Player(0),
+            Cell::Wall,
+            Cell::Player(1),
+            Cell::Wall,
+            Cell::Wall,
+            Cell::Wall,
+            Cell::Player(2),
+            Cell::Wall,
+            Cell::Player(3),
+        ]
+        .into_iter();
+        for yn in y - 1..=y + 1 {
+            for xn in x - 1..=x + 1 {
+                let cell = if xn < 0 || yn < 0 {
+                    None
+                } else {
+                    self.cart
+                        .get_mut(yn as usize)
+                        .and_then(|v| v.get_mut(xn as usize))
+                };
+                match cell {
+                    Some(c) if *c == Cell::Empty || (xn, yn) == (x, y) => {
+                        *c = replacement.next().unwrap_or(Cell::Wall)
+                    }
+                    _ => {
+                        return Err(StrError {
+                            msg: "the entrance must be surrounded by open floor to split it"
+                                .to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn to_input(&self) -> String {
+        let mut input = String::new();
+        for row in &self.cart {
+            input.extend(row.iter().map(|c| c.to_input_char()));
+            input.push('\n');
+        }
+        input
+    }
+
+    fn robots(&self) -> Vec<(i16, i16)> {
+        let mut robots = Vec::new();
+        for (y, row) in self.cart.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                if let Cell::Player(n) = cell {
+                    robots.push((*n, (x as i16, y as i16)));
+                }
+            }
+        }
+        robots.sort();
+        robots.into_iter().map(|(_, p)| p).collect()
+    }
+
+    fn keys(&self) -> Vec<char> {
+        let mut keys: Vec<char> = self
+            .cart
+            .iter()
+            .flatten()
+            .filter_map(|c| if let Cell::Key(k) = c { Some(*k) } else { None })
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    // every shortest route from (x, y) to each key, with the keys and doors passed on the way.
+    // a vault with loops can have several routes between the same two spots, so every route
+    // that isn't beaten on both distance and requirements is kept
+    fn key_routes(&self, (x, y): (i16, i16), bit: impl Fn(char) -> u64) -> Vec<Route> {
+        let mut routes = Vec::new();
+        let mut labels: Vec<Vec<Vec<(usize, u64, u64)>>> = self
+            .cart
+            .iter()
+            .map(|v| vec![Vec::new(); v.len()])
+            .collect();
+        labels[y as usize][x as usize].push((0, 0, 0));
+        let mut queue = VecDeque::from([((x, y), 0, 0, 0)]);
+        while let Some(((xc, yc), d, keys, doors)) = queue.pop_front() {
+            for (xn, yn) in [(xc + 1, yc), (xc, yc + 1), (xc - 1, yc), (xc, yc - 1)] {
+                if xn < 0 || yn < 0 {
+                    continue;
+                }
+                let (passed, opened) =
+                    match self.cart.get(yn as usize).and_then(|v| v.get(xn as usize)) {
+                        None | Some(Cell::Wall) => continue,
+                        Some(Cell::Key(c)) => (keys | bit(*c), doors),
+                        Some(Cell::Door(c)) => (keys, doors | bit(*c)),
+                        Some(_) => (keys, doors),
+                    };
+                let here = &mut labels[yn as usize][xn as usize];
+                if here
+                    .iter()
+                    .any(|(d0, k0, o0)| *d0 <= d + 1 && k0 & !passed == 0 && o0 & !opened == 0)
+                {
+                    continue;
+                }
+                here.push((d + 1, passed, opened));
+                if let Cell::Key(c) = self.cart[yn as usize][xn as usize] {
+                    routes.push((c, (xn, yn), d + 1, keys, opened));
+                }
+                queue.push_back(((xn, yn), d + 1, passed, opened));
+            }
+        }
+        routes
+    }
+
+    fn solve(&self, limit: usize) -> Result<Option<Solution>, StrError> {
+        let goal = (1 << self.keys().len()) - 1;
+        let mut budget = limit;
+        self.solve_from(
+            &self.robots(),
+            0,
+            goal,
+            |held| held,
+            &mut budget,
+            &AtomicBool::new(false),
+        )
+    }
+
+    // the fewest steps for robots standing at robots and already holding the keys in held to pick up
+    // every key in goal, where open(keys) is the doors that are open once those keys are picked up.
+    // keys and doors are bits in the order of self.keys(). each state looked at comes out of budget,
+    // and running out of it is an error rather than None, which means there's no way through.
+    // so is stop being set, by whoever no longer wants the answer
+    fn solve_from(
+        &self,
+        robots: &[(i16, i16)],
+        held: u64,
+        goal: u64,
+        open: impl Fn(u64) -> u64,
+        budget: &mut usize,
+        stop: &AtomicBool,
+    ) -> Result<Option<Solution>, StrError> {
+        // a state is where each robot is (8 bits apiece, as indices into spots) and which keys are held
+        type State = (u64, u64);
+
+        let keys = self.keys();
+        if robots.len() > 8 {
+            return Ok(None);
+        }
+        let mut spots = robots.to_vec();
+        for c in &keys {
+            for (y, row) in self.cart.iter().enumerate() {
+                for (x, cell) in row.iter().enumerate() {
+                    if *cell == Cell::Key(*c) {
+                        spots.push((x as i16, y as i16));
+                    }
+                }
+            }
+        }
+        let index = |c: char| keys.binary_search(&c).unwrap_or(0);
+        let bit = |c: char| 1u64 << index(c);
+
+        // (key index, distance, keys passed, doors passed) from each spot
+        let routes: Vec<Vec<(usize, usize, u64, u64)>> = spots
+            .iter()
+            .map(|p| {
+                self.key_routes(*p, bit)
+                    .into_iter()
+                    .map(|(c, _, d, k, o)| (index(c), d, k, o))
+                    .collect()
+            })
+            .collect();
+        let mut dist = vec![vec![usize::MAX; keys.len()]; spots.len()];
+        for (from, rs) in routes.iter().enumerate() {
+            for (k, d, _, _) in rs {
+                dist[from][*k] = dist[from][*k].min(*d);
+            }
+        }
+        // keys that only one robot can ever reach, which that robot has to walk to sooner or later
+        let owner: Vec<Option<usize>> = (0..keys.len())
+            .map(|k| {
+                let reach: Vec<usize> = (0..robots.len())
+                    .filter(|r| dist[*r][k] != usize::MAX)
+                    .collect();
+                if reach.len() == 1 {
+                    Some(reach[0])
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let at = |positions: u64, r: usize| (positions >> (8 * r) & 0xff) as usize;
+        // the farthest key each robot still has to reach is a lower bound on the steps left
+        let estimate = |(positions, mask): State| -> usize {
+            let mut far = vec![0; robots.len()];
+            for (k, o) in owner.iter().enumerate() {
+                if let Some(r) = o
+                    && (goal & !mask) & (1 << k) != 0
+                {
+                    far[*r] = far[*r].max(dist[at(positions, *r)][k]);
+                }
+            }
+            far.iter().sum()
+        };
+
+        let start: State = (
+            (0..robots.len()).fold(0, |p, r| p | (r as u64) << (8 * r)),
+            held,
+        );
+        let mut best: HashMap<State, usize> = HashMap::from([(start, 0)]);
+        let mut prev: HashMap<State, (State, usize, char)> = HashMap::new();
+        let mut heap = BinaryHeap::from([Reverse((estimate(start), 0, start))]);
+
+        while let Some(Reverse((_, d, state))) = heap.pop() {
+            if best.get(&state).is_some_and(|b| *b < d) {
+                continue;
+            }
+            let (positions, mask) = state;
+            if mask & goal == goal {
+                let mut order = Vec::new();
+                let mut current = state;
+                while let Some((p, n, c)) = prev.get(&current) {
+                    order.push((*n, *c));
+                    current = *p;
+                }
+                order.reverse();
+                return Ok(Some(Solution { steps: d, order }));
+            }
+            let opened = open(mask);
+            for n in 0..robots.len() {
+                for (k, steps, passed, doors) in &routes[at(positions, n)] {
+                    if mask & (1 << k) != 0 || passed & !mask != 0 || doors & !opened != 0 {
+                        continue;
+                    }
+                    let moved =
+                        positions & !(0xff << (8 * n)) | ((robots.len() + k) as u64) << (8 * n);
+                    let next: State = (moved, mask | 1 << k);
+                    if best.get(&next).is_none_or(|b| d + steps < *b) {
+                        if *budget == 0 {
+                            return Err(StrError {
+                                msg:
+                                    "gave up looking for the best route, there are too many to try"
+                                        .to_string(),
+                            });
+                        }
+                        if stop.load(Ordering::Relaxed) {
+                            return Err(StrError {
+                                msg: "stopped looking for the best route".to_string(),
+                            });
+                        }
+                        *budget -= 1;
+                        best.insert(next, d + steps);
+                        prev.insert(next, (state, n, keys[*k]));
+                        heap.push(Reverse((d + steps + estimate(next), d + steps, next)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn carve(
+        &mut self,
+        x: i16,
+        y: i16,
+        dx: bool,
+        dy: bool,
+        algorithm: MazeAlgorithm,
+        rng: &mut impl MazeRng,
+    ) {
+        if algorithm == MazeAlgorithm::Backtracker {
+            return self.backtrack(x, y, dx, dy, rng);
+        }
+        let cells = self.quadrant_cells(x, y, dx, dy);
+        let passages = algorithm.passages(&cells, (x, y), rng);
+
+        self.cart[y as usize][x as usize] = Cell::Empty;
+        self.tree.insert((x, y), None);
+        let mut links: HashMap<(i16, i16), Vec<(i16, i16)>> = HashMap::new();
+        for (a, b) in passages {
+            links.entry(a).or_default().push(b);
+            links.entry(b).or_default().push(a);
+        }
+        let mut queue = VecDeque::from([(x, y)]);
+        while let Some((xc, yc)) = queue.pop_front() {
+            for &(xn, yn) in links.get(&(xc, yc)).into_iter().flatten() {
+                let (xw, yw) = ((xc + xn) / 2, (yc + yn) / 2);
+                if self.tree.contains_key(&(xw, yw)) {
+                    continue;
+                }
+                self.tree.insert((xw, yw), Some((xc, yc)));
+                self.tree.insert((xn, yn), Some((xw, yw)));
+                self.cart[yw as usize][xw as usize] = Cell::Empty;
+                self.cart[yn as usize][xn as usize] = Cell::Empty;
+                queue.push_back((xn, yn));
+            }
+        }
+    }
+
+    // knocks the wall out of a dead end into a neighbouring cell, for braid% of dead ends
+    fn braid(&mut self, options: &GridOptions, rng: &mut impl MazeRng) {
+        let (cx, cy) = options.centre();
+        for (x, y) in options.starts() {
+            let cells = self.quadrant_cells(x, y, x > cx, y > cy);
+            let cellset: HashSet<(i16, i16)> = cells.iter().copied().collect();
+            for (xc, yc) in cells {
+                let walls: Vec<(i16, i16)> = cell_neighbours((xc, yc))
+                    .into_iter()
+                    .filter(|(xn, yn)| cellset.contains(&(*xn, *yn)))
+                    .map(|(xn, yn)| ((xc + xn) / 2, (yc + yn) / 2))
+                    .filter(|(xw, yw)| self.cart[*yw as usize][*xw as usize] == Cell::Wall)
+                    .collect();
+                let exits = [(xc + 1, yc), (xc, yc + 1), (xc - 1, yc), (xc, yc - 1)]
+                    .iter()
+                    .filter(|(xn, yn)| self.cart[*yn as usize][*xn as usize] != Cell::Wall)
+                    .count();
+                if exits == 1
+                    && rng.chance(options.braid)
+                    && let Some((xw, yw)) = rng.pick(&walls)
+                {
+                    self.cart[*yw as usize][*xw as usize] = Cell::Empty;
+                }
+            }
+        }
+    }
+
+    // every cell a quadrant's maze can visit, starting from (x, y) and growing towards (dx, dy)
+    fn quadrant_cells(&self, x: i16, y: i16, dx: bool, dy: bool) -> Vec<(i16, i16)> {
+        let (w, h) = (self.cart[0].len() as i16, self.cart.len() as i16);
+        let xs: Vec<i16> = if dx {
+            (x..w - 1).step_by(2).collect()
+        } else {
+            (1..=x).rev().step_by(2).collect()
+        };
+        let ys: Vec<i16> = if dy {
+            (y..h - 1).step_by(2).collect()
+        } else {
+            (1..=y).rev().step_by(2).collect()
+        };
+        ys.iter()
+            .flat_map(|yc| xs.iter().map(move |xc| (*xc, *yc)))
+            .collect()
+    }
+
+    fn backtrack(&mut self, x: i16, y: i16, dx: bool, dy: bool, rng: &mut impl MazeRng) {
+        self.cart[y as usize][x as usize] = Cell::Empty;
+        let mut stack: Vec<(i16, i16)> = Vec::new();
+        stack.push((x, y));
+        self.tree.insert((x, y), None);
+        loop {
+            let cur = stack.pop();
+            match cur {
+                None => break,
+                Some((xc, yc)) => {
+                    let valid_neighbour = |&&(xn, yn): &&(i16, i16)| {
+                        self.cart
+                            .get((2 * yn - yc) as usize)
+                            .and_then(|v| v.get((2 * xn - xc) as usize))
+                            == Some(&Cell::Wall)
+                            && (xn == xc || ((xn > x) == dx))
+                            && (yn == yc || ((yn > y) == dy))
+                    };
+                    let candidates = [(xc + 1, yc), (xc, yc + 1), (xc - 1, yc), (xc, yc - 1)];
+                    let neighbours: Vec<(i16, i16)> =
+                        candidates.iter().filter(valid_neighbour).copied().collect();
+                    match rng.pick(&neighbours) {
+                        None => continue,
+                        Some(&(xn, yn)) => {
+                            stack.push((xc, yc));
+                            stack.push((2 * xn - xc, 2 * yn - yc));
+                            self.tree.insert((xn, yn), Some((xc, yc)));
+                            self.tree.insert((2 * xn - xc, 2 * yn - yc), Some((xn, yn)));
+                            self.cart[yn as usize][xn as usize] = Cell::Empty;
+                            self.cart[(2 * yn - yc) as usize][(2 * xn - xc) as usize] = Cell::Empty;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MazeAlgorithm {
+    Backtracker,
+    Prim,
+    Kruskal,
+    Wilson,
+    GrowingTree,
+}
+type Passage = ((i16, i16), (i16, i16));
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Difficulty {
+    Easy,   // keeps the placement that scores lowest
+    Normal, // keeps the placement that scores highest
+    Hard,   // same, but scores on chain depth rather than requirements
+}
+
+impl Difficulty {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+        }
+    }
+
+    fn objective(self) -> Objective {
+        match self {
+            Difficulty::Hard => Objective::ChainDepth,
+            Difficulty::Easy | Difficulty::Normal => Objective::Requirements,
+        }
+    }
+}
+
+// what key placement in generate_grid tries to make the most of
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Objective {
+    Requirements,  // total doors needed across all keys
+    ChainDepth,    // longest run of keys each needed for the next
+    RobotSwitches, // most times that run has to move to another robot
+    PathLength,    // steps in the optimal solution
+    Balanced,      // requirements of the least demanding quadrant
+}
+
+impl Objective {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "requirements" => Some(Objective::Requirements),
+            "chain-depth" | "chain_depth" => Some(Objective::ChainDepth),
+            "robot-switches" | "robot_switches" => Some(Objective::RobotSwitches),
+            "path-length" | "path_length" => Some(Objective::PathLength),
+            "balanced" => Some(Objective::Balanced),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Objective::Requirements => "requirements",
+            Objective::ChainDepth => "chain-depth",
+            Objective::RobotSwitches => "robot-switches",
+            Objective::PathLength => "path-length",
+            Objective::Balanced => "balanced",
+        }
+    }
+}
+
+// the heaviest run of keys through logic, where weight(d, k) is what needing key d before key k adds.
+// logic has to be consistent, or this never finishes
+fn longest_chain(logic: &Logic, weight: impl Fn(&char, &char) -> usize) -> usize {
+    fn chain(
+        k: &char,
+        logic: &Logic,
+        weight: &impl Fn(&char, &char) -> usize,
+        memo: &mut HashMap<char, usize>,
+    ) -> usize {
+        if let Some(n) = memo.get(k) {
+            return *n;
+        }
+        let mut n = 0;
+        for d in logic.get(k).into_iter().flatten() {
+            n = n.max(chain(d, logic, weight, memo) + weight(d, k));
+        }
+        memo.insert(*k, n);
+        n
+    }
+    let mut memo = HashMap::new();
+    logic
+        .keys()
+        .map(|k| chain(k, logic, &weight, &mut memo))
+        .max()
+        .unwrap_or(0)
+}
+
+fn cell_neighbours((x, y): (i16, i16)) -> [(i16, i16); 4] {
+    [(x + 2, y), (x, y + 2), (x - 2, y), (x, y - 2)]
+}
+
+impl MazeAlgorithm {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "backtracker" => Some(MazeAlgorithm::Backtracker),
+            "prim" => Some(MazeAlgorithm::Prim),
+            "kruskal" => Some(MazeAlgorithm::Kruskal),
+            "wilson" => Some(MazeAlgorithm::Wilson),
+            "growing-tree" | "growing_tree" => Some(MazeAlgorithm::GrowingTree),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            MazeAlgorithm::Backtracker => "backtracker",
+            MazeAlgorithm::Prim => "prim",
+            MazeAlgorithm::Kruskal => "kruskal",
+            MazeAlgorithm::Wilson => "wilson",
+            MazeAlgorithm::GrowingTree => "growing-tree",
+        }
+    }
+
+    // the passages of a spanning tree over cells (every other tile, so neighbours are 2 apart).
+    // the backtracker isn't here because it carves the grid directly, to keep old seeds stable
+    fn passages(
+        self,
+        cells: &[(i16, i16)],
+        start: (i16, i16),
+        rng: &mut impl MazeRng,
+    ) -> Vec<Passage> {
+        let cellset: HashSet<(i16, i16)> = cells.iter().copied().collect();
+        let mut passages = Vec::new();
+        match self {
+            MazeAlgorithm::Backtracker => {}
+            MazeAlgorithm::Prim => {
+                let mut visited = HashSet::from([start]);
+                let mut frontier: Vec<(i16, i16)> = cell_neighbours(start)
+                    .into_iter()
+                    .filter(|n| cellset.contains(n))
+                    .collect();
+                while !frontier.is_empty() {
+                    let f = rng.take(&mut frontier);
+                    let links: Vec<(i16, i16)> = cell_neighbours(f)
+                        .into_iter()
+                        .filter(|n| visited.contains(n))
+                        .collect();
+                    if let Some(&l) = rng.pick(&links) {
+                        passages.push((l, f));
+                    }
+                    visited.insert(f);
+                    for n in cell_neighbours(f) {
+                        if cellset.contains(&n) && !visited.contains(&n) && !frontier.contains(&n) {
+                            frontier.push(n);
+                        }
+                    }
+                }
+            }
+            MazeAlgorithm::Kruskal => {
+                let mut edges: Vec<Passage> = Vec::new();
+                for &(x, y) in cells {
+                    for n in [(x + 2, y), (x, y + 2)] {
+                        if cellset.contains(&n) {
+                            edges.push(((x, y), n));
+                        }
+                    }
+                }
+                rng.shuffle(&mut edges);
+
+                let mut parent: HashMap<(i16, i16), (i16, i16)> = HashMap::new();
+                let find = |parent: &HashMap<(i16, i16), (i16, i16)>, mut c: (i16, i16)| {
+                    while let Some(p) = parent.get(&c) {
+                        c = *p;
+                    }
+                    c
+                };
+                for (a, b) in edges {
+                    let (ra, rb) = (find(&parent, a), find(&parent, b));
+                    if ra != rb {
+                        parent.insert(ra, rb);
+                        passages.push((a, b));
+                    }
+                }
+            }
+            MazeAlgorithm::Wilson => {
+                let mut in_maze = HashSet::from([start]);
+                let mut order = cells.to_vec();
+                rng.shuffle(&mut order);
+                for c in order {
+                    // random walk until the maze is hit, remembering only the last exit from each cell
+                    let mut next: HashMap<(i16, i16), (i16, i16)> = HashMap::new();
+                    let mut current = c;
+                    while !in_maze.contains(&current) {
+                        let options: Vec<(i16, i16)> = cell_neighbours(current)
+                            .into_iter()
+                            .filter(|n| cellset.contains(n))
+                            .collect();
+                        match rng.pick(&options) {
+                            None => break,
+                            Some(&n) => {
+                                next.insert(current, n);
+                                current = n;
+                            }
+                        }
+                    }
+                    let mut current = c;
+                    while !in_maze.contains(&current)
+                        && let Some(n) = next.get(&current)
+                    {
+                        in_maze.insert(current);
+                        passages.push((current, *n));
+                        current = *n;
+                    }
+                }
+            }
+            MazeAlgorithm::GrowingTree => {
+                // picks the newest cell half of the time (like the backtracker) and a random one otherwise (like prim)
+                let mut visited = HashSet::from([start]);
+                let mut active = vec![start];
+                while !active.is_empty() {
+                    let i = if rng.coin() {
+                        active.len() - 1
+                    } else {
+                        rng.below(active.len())
+                    };
+                    let options: Vec<(i16, i16)> = cell_neighbours(active[i])
+                        .into_iter()
+                        .filter(|n| cellset.contains(n) && !visited.contains(n))
+                        .collect();
+                    match rng.pick(&options) {
+                        None => {
+                            active.remove(i);
+                        }
+                        Some(&n) => {
+                            visited.insert(n);
+                            passages.push((active[i], n));
+                            active.push(n);
+                        }
+                    }
+                }
+            }
+        }
+        passages
+    }
+}
+
+struct GridState {
+    all_keys: Vec<char>,
+    grid: Grid,
+    player: usize,
+    players: [(i16, i16); 4],
+    keys: HashSet<char>,
+    msgs: VecDeque<RichPrint>,
+    finished: bool,
+    stats: Stats,
+    singleplayer: bool,
+    history: Vec<Move>, // singleplayer moves, so they can be undone
+    undone: Vec<Move>,
+    goto: Option<String>,         // what's been typed at the go to prompt
+    travel: VecDeque<(i16, i16)>, // where the active robot is still to go by itself
+    overlay: bool,                // showing what the robots can reach
+    view: View,
+    explored: Option<HashSet<(i16, i16)>>, // what the robots have seen, when playing in the fog
+    banner: Option<String>,                // shown over the vault, like while reconnecting
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum View {
+    Single, // following the active robot
+    Split,  // following each robot in a quarter of the screen
+    Map,    // the whole vault, shrunk to fit
+}
+
+struct Move {
+    robot: usize,
+    from: (i16, i16),
+    to: (i16, i16),
+    key: Option<char>,   // picked up by the move
+    last: Option<usize>, // the robot that moved before it
+}
+
+#[derive(Clone, Default)]
+struct Stats {
+    steps: [usize; 4],   // per robot
+    keys: [usize; 4],    // picked up per robot
+    switches: usize,     // times the robot moving wasn't the one that moved before
+    last: Option<usize>, // the robot that moved last
+    route: Vec<Pickup>,
+    arrivals: Vec<(char, usize)>, // keys received in a multiworld, with how many had been picked up by then
+}
+
+#[derive(Clone)]
+struct Pickup {
+    robot: usize,
+    key: char,
+    steps: usize,             // in total, once it was picked up
+    players: [(i16, i16); 4], // where every robot was then
+}
+
+impl Stats {
+    fn total(&self) -> usize {
+        self.steps.iter().sum()
+    }
+
+    fn moved(&mut self, robot: usize) {
+        if self.last.is_some_and(|l| l != robot) {
+            self.switches += 1;
+        }
+        self.last = Some(robot);
+        self.steps[robot] += 1;
+    }
+
+    // how the route taken compares with the best one through original, the vault as it started.
+    // in a multiworld a door can't be opened any sooner than its key arrived, which is counted
+    // in keys picked up rather than time, as that's what makes the other worlds send them
+    fn report(&self, original: &Grid, multiworld: bool, stop: &AtomicBool) -> Vec<String> {
+        self.compare(original, multiworld, &mut { REPORT_LIMIT }, stop)
+            .unwrap_or_else(|_| {
+                vec![
+                    "No comparison available, there are too many routes through this vault to find the best one."
+                        .to_string(),
+                ]
+            })
+    }
+
+    // the solver only gets budget states between all its runs here, as on vaults with loops
+    // it can take a long time over each of them
+    fn compare(
+        &self,
+        original: &Grid,
+        multiworld: bool,
+        budget: &mut usize,
+        stop: &AtomicBool,
+    ) -> Result<Vec<String>, StrError> {
+        let keys = original.keys();
+        let bit = |c: &char| keys.binary_search(c).map_or(0, |i| 1u64 << i);
+        let route = &self.route;
+        let goal = if multiworld {
+            route.iter().fold(0, |m, p| m | bit(&p.key))
+        } else {
+            keys.iter().fold(0, |m, c| m | bit(c))
+        };
+        let arrived: Vec<usize> = keys
+            .iter()
+            .map(|c| {
+                self.arrivals
+                    .iter()
+                    .find(|(a, _)| a == c)
+                    .map_or(usize::MAX, |(_, n)| *n)
+            })
+            .collect();
+        let open = |held: u64| {
+            if multiworld {
+                let n = held.count_ones() as usize;
+                (0..keys.len())
+                    .filter(|i| arrived[*i] <= n)
+                    .fold(0, |m, i| m | 1 << i)
+            } else {
+                held
+            }
+        };
+        // the best that could be done from where things stood after the first n pickups
+        let best_after =
+            |n: usize, budget: &mut usize| -> Result<Option<(usize, Solution)>, StrError> {
+                let (robots, taken) = match n.checked_sub(1).map(|i| &route[i]) {
+                    None => (original.robots(), 0),
+                    Some(p) => (p.players.to_vec(), p.steps),
+                };
+                let held = route[..n].iter().fold(0, |m, p| m | bit(&p.key));
+                // no one run gets more than SOLVE_LIMIT, which is as much memory as a run should take
+                let mut run = (*budget).min(SOLVE_LIMIT);
+                let start = run;
+                let best = original.solve_from(&robots, held, goal, open, &mut run, stop);
+                *budget -= start - run;
+                Ok(best?.map(|s| (taken + s.steps, s)))
+            };
+
+        let Some((optimal, _)) = best_after(0, budget)? else {
+            return Ok(vec![
+                "There's no route through this vault to compare yours with.".to_string(),
+            ]);
+        };
+        let yours = route.last().map_or(0, |p| p.steps);
+        let mut report = vec![format!(
+            "The best route takes {optimal} steps, and yours took {yours} ({:.1}% efficient).",
+            100.0 * optimal as f64 / yours.max(1) as f64
+        )];
+
+        // once a route falls behind it can't catch up, so the first pickup behind can be searched for
+        let (mut lo, mut hi) = (1, route.len() + 1);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if best_after(mid, budget)?.is_none_or(|(b, _)| b > optimal) {
+                hi = mid;
+            } else {
+                lo = mid + 1;
+            }
+        }
+        if lo > route.len() {
+            report.push("You didn't take a single step more than you had to!".to_string());
+        } else {
+            let p = &route[lo - 1];
+            let lost = best_after(lo, budget)?.map(|(b, _)| b - optimal);
+            report.push(match lost {
+                Some(lost) => format!(
+                    "You left the best route on the way to key {} (robot {}), {lost} steps behind from there.",
+                    p.key, p.robot
+                ),
+                None => format!(
+                    "You left the best route on the way to key {} (robot {}).",
+                    p.key, p.robot
+                ),
+            });
+            if let Some((_, s)) = best_after(lo - 1, budget)?
+                && let Some((n, c)) = s.order.first()
+            {
+                report.push(if (*n, *c) == (p.robot, p.key) {
+                    format!("There was a shorter way for robot {n} to get to key {c}.")
+                } else {
+                    format!("Robot {n} going for key {c} would have been better.")
+                });
+            }
+        }
+        Ok(report)
+    }
+}
+
+impl GridState {
+    fn draw(&self, screen: &mut Screen, slot: &String) -> Result<(), StrError> {
+        let keystring: Vec<char> = self
+            .all_keys
+            .iter()
+            .map(|c| if self.keys.contains(c) { *c } else { ' ' })
+            .collect();
+
+        let highlight = if self.finished {
+            Rgb {
+                r: 235,
+                g: 255,
+                b: 108,
+            }
+        } else {
+            Rgb {
+                r: 255,
+                g: 255,
+                b: 255,
+            }
+        };
+        let mut sidebar = vec![
+            ("[wasd]/arrows to move".to_string(), Color::Reset),
+            ("[c] change robot [g] go to".to_string(), Color::Reset),
+            ("[o] overlay [v] view".to_string(), Color::Reset),
+            (
+                if self.singleplayer {
+                    "[u]/[r] undo/redo [q] quit"
+                } else {
+                    "[q] to quit"
+                }
+                .to_string(),
+                Color::Reset,
+            ),
+            ("keys collected:".to_string(), Color::Reset),
+        ];
+        for chunk in keystring.chunks(26) {
+            sidebar.push((chunk.iter().collect(), highlight));
+        }
+        sidebar.push((String::new(), Color::Reset));
+        sidebar.push((format!("steps: {}", self.stats.total()), Color::Reset));
+        sidebar.push((
+            format!("robot switches: {}", self.stats.switches),
+            Color::Reset,
+        ));
+        sidebar.push(("robot  steps  keys".to_string(), Color::Reset));
+        for n in 0..4 {
+            sidebar.push((
+                format!(
+                    "{}{n}{:>10}{:>6}",
+                    if self.player == n { '>' } else { ' ' },
+                    self.stats.steps[n],
+                    self.stats.keys[n]
+                ),
+                Color::Reset,
+            ));
+        }
+
+        // the map starts just past the widest line of the sidebar, and the sidebar has to fit above the messages
+        let offset = sidebar
+            .iter()
+            .map(|(line, _)| line.chars().count())
+            .max()
+            .unwrap_or(0) as i16
+            + 1;
+        let (cols, rows) = screen.begin()?;
+        let (least_cols, least_rows) = (offset + 21, sidebar.len() as i16 + 6);
+        if cols < least_cols || rows < least_rows {
+            let lines = [
+                "terminal too small".to_string(),
+                format!("{cols}x{rows}, needs {least_cols}x{least_rows}"),
+            ];
+            for (line, y) in lines.iter().zip(rows / 2 - 1..) {
+                let x = (cols - line.chars().count() as i16).max(0) / 2;
+                screen.print(x, y, line, Color::Reset);
+            }
+            screen.present()?;
+            return Ok(());
+        }
+        let width = cols - offset - 1;
+        let height = rows - 6;
+
+        for ((line, col), y) in sidebar.iter().zip(0..) {
+            screen.print(0, y, line, *col);
+        }
+        for (msg, y) in self.msgs.iter().zip(height..) {
+            rich_print(screen, y, slot, msg);
+        }
+        screen.print(
+            0,
+            height + 5,
+            &if let Some(input) = &self.goto {
+                let (x, y) = self.players[self.player];
+                let keys: String = self.reachable_keys().into_iter().collect();
+                format!("go to key [{keys}], or x,y (you're at {x},{y}): {input}")
+            } else if self.finished {
+                format!(
+                    "You have found all of your keys! {} steps, {} robot switches",
+                    self.stats.total(),
+                    self.stats.switches
+                )
+            } else {
+                String::new()
+            },
+            highlight,
+        );
+
+        // everywhere any robot can get to, and the keys there
+        let reached: HashSet<(i16, i16)> = if self.overlay {
+            self.players
+                .iter()
+                .flat_map(|p| self.flood(*p).into_keys())
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        let reachable: HashSet<char> = reached
+            .iter()
+            .filter_map(|(x, y)| match self.grid.cart[*y as usize][*x as usize] {
+                Cell::Key(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+
+        match self.view {
+            View::Single => self.draw_area(
+                screen,
+                (offset, 0, width, height),
+                self.players[self.player],
+                &reached,
+                &reachable,
+            ),
+            View::Split => {
+                // robot n's corner of the vault is in the same corner of the screen
+                let (w, h) = (width / 2, height / 2);
+                for n in 0..4 {
+                    let (left, top) = (offset + w * (n % 2) as i16, h * (n / 2) as i16);
+                    let (w, h) = (
+                        if n % 2 == 0 { w } else { width - w },
+                        if n / 2 == 0 { h } else { height - h },
+                    );
+                    self.draw_frame(screen, (left, top, w, h), n == self.player);
+                    self.draw_area(
+                        screen,
+                        (left + 1, top + 1, w - 2, h - 2),
+                        self.players[n],
+                        &reached,
+                        &reachable,
+                    );
+                }
+            }
+            View::Map => self.draw_map(screen, (offset, 0, width, height), &reached, &reachable),
+        }
+        if let Some(banner) = &self.banner {
+            let x = offset + (width - banner.chars().count() as i16).max(0) / 2;
+            screen.print(
+                x,
+                0,
+                banner,
+                Rgb {
+                    r: 255,
+                    g: 90,
+                    b: 90,
+                },
+            );
+        }
+        // the minimap takes whatever's left of the sidebar under the robots
+        let top = sidebar.len() as i16 + 1;
+        if height - top >= 3 {
+            self.draw_map(
+                screen,
+                (0, top, offset - 1, height - top),
+                &reached,
+                &reachable,
+            );
+        }
+        screen.present()?;
+        Ok(())
+    }
+
+    // the vault around centre, in the part of the screen at (left, top, width, height)
+    fn draw_area(
+        &self,
+        screen: &mut Screen,
+        (left, top, width, height): (i16, i16, i16, i16),
+        (xp, yp): (i16, i16),
+        reached: &HashSet<(i16, i16)>,
+        reachable: &HashSet<char>,
+    ) {
+        let (xs, ys) = (left + width / 2, top + height / 2);
+        let green = Rgb {
+            r: 108,
+            g: 255,
+            b: 108,
+        };
+        let yellow = Rgb {
+            r: 255,
+            g: 220,
+            b: 80,
+        };
+        let red = Rgb {
+            r: 255,
+            g: 90,
+            b: 90,
+        };
+        let dim = Rgb {
+            r: 80,
+            g: 80,
+            b: 80,
+        };
+
+        for y in top..top + height {
+            for x in left..left + width {
+                let distfactor: f64 = (((x - xs) as f64).powf(2.0)
+                    + ((2 * y - 2 * ys) as f64).powf(2.0))
+                .clamp(1.0, 255.0);
+                let k: f64 = 3.9;
+                let col_from = |n: f64| Rgb {
+                    r: (k * n / distfactor) as u8,
+                    g: (k * n / distfactor) as u8,
+                    b: (k * n / distfactor) as u8,
+                };
+
+                let cell = if (y - ys + yp) < 0 || (x - xs + xp) < 0 {
+                    &Cell::Wall
+                } else {
+                    self.grid
+                        .cart
+                        .get((y - ys + yp) as usize)
+                        .and_then(|v| v.get((x - xs + xp) as usize))
+                        .unwrap_or(&Cell::Wall)
+                };
+                let col = match cell {
+                    Cell::Player(n) => {
+                        if self.player == *n as usize {
+                            col_from(255.0)
+                        } else {
+                            col_from(128.0)
+                        }
+                    }
+                    Cell::Wall | Cell::Empty => col_from(64.0),
+                    Cell::Key(_) | Cell::Door(_) => col_from(255.0),
+                };
+                let (gx, gy) = (x - xs + xp, y - ys + yp);
+                // the overlay isn't darkened away from the robot, so it can be seen all over the map
+                let (col, c) = match cell {
+                    _ if !self.overlay => (col, cell.to_char()),
+                    Cell::Empty if reached.contains(&(gx, gy)) => (col_from(128.0), '.'),
+                    Cell::Key(c) if reachable.contains(c) => (green, *c),
+                    // a door the robots are up against shows the key that opens it
+                    Cell::Door(c)
+                        if [(0, -1), (1, 0), (0, 1), (-1, 0)]
+                            .iter()
+                            .any(|(dx, dy)| reached.contains(&(gx + dx, gy + dy))) =>
+                    {
+                        (if reachable.contains(c) { yellow } else { red }, *c)
+                    }
+                    Cell::Key(_) | Cell::Door(_) => (dim, cell.to_char()),
+                    _ => (col, cell.to_char()),
+                };
+                let (col, c) = if self.seen(gx, gy) {
+                    (col, c)
+                } else {
+                    (Rgb { r: 0, g: 0, b: 0 }, ' ')
+                };
+                screen.put(x, y, c, col, Rgb { r: 0, g: 0, b: 0 });
+            }
+        }
+    }
+
+    fn draw_frame(
+        &self,
+        screen: &mut Screen,
+        (left, top, width, height): (i16, i16, i16, i16),
+        active: bool,
+    ) {
+        let line = |l: char, m: char, r: char| {
+            if active {
+                format!(
+                    "{l}{}{r}",
+                    m.to_string().repeat((width - 2).max(0) as usize)
+                )
+            } else {
+                " ".repeat(width.max(0) as usize)
+            }
+        };
+        let white = Rgb {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        let black = Rgb { r: 0, g: 0, b: 0 };
+        screen.print(left, top, &line('┌', '─', '┐'), white);
+        screen.print(left, top + height - 1, &line('└', '─', '┘'), white);
+        let side = if active { '│' } else { ' ' };
+        for y in top + 1..top + height - 1 {
+            screen.put(left, y, side, white, black);
+            screen.put(left + width - 1, y, side, white, black);
+        }
+    }
+
+    // the whole vault, as many cells to a character as it takes to fit. each character is two
+    // squares, one above the other: the foreground of a half block and the background behind it
+    fn draw_map(
+        &self,
+        screen: &mut Screen,
+        (left, top, width, height): (i16, i16, i16, i16),
+        reached: &HashSet<(i16, i16)>,
+        reachable: &HashSet<char>,
+    ) {
+        let (w, h) = (self.grid.cart[0].len(), self.grid.cart.len());
+        let (cols, rows) = (width.max(1) as usize, height.max(1) as usize);
+        let scale = w.div_ceil(cols).max(h.div_ceil(2 * rows)).max(1);
+        let (mw, mh) = (w.div_ceil(scale) as i16, h.div_ceil(2 * scale) as i16);
+        let (ml, mt) = (left + (width - mw) / 2, top + (height - mh) / 2);
+        let (w, h, scale) = (w as i16, h as i16, scale as i16);
+
+        // what stands out most in a square of cells
+        let shade = |gx: i16, gy: i16| {
+            let cells = (gy..(gy + scale).min(h))
+                .flat_map(|y| (gx..(gx + scale).min(w)).map(move |x| (x, y)));
+            let mut col = Rgb { r: 0, g: 0, b: 0 };
+            let mut rank = 0;
+            for (x, y) in cells.filter(|(x, y)| self.seen(*x, *y)) {
+                let (r, c) = match self.grid.cart[y as usize][x as usize] {
+                    Cell::Player(n) if n as usize == self.player => (
+                        5,
+                        Rgb {
+                            r: 255,
+                            g: 255,
+                            b: 255,
+                        },
+                    ),
+                    Cell::Player(_) => (
+                        4,
+                        Rgb {
+                            r: 170,
+                            g: 170,
+                            b: 170,
+                        },
+                    ),
+                    Cell::Key(c) if self.overlay && !reachable.contains(&c) => (
+                        3,
+                        Rgb {
+                            r: 80,
+                            g: 80,
+                            b: 80,
+                        },
+                    ),
+                    Cell::Key(_) => (
+                        3,
+                        Rgb {
+                            r: 108,
+                            g: 255,
+                            b: 108,
+                        },
+                    ),
+                    Cell::Door(_) => (
+                        2,
+                        Rgb {
+                            r: 255,
+                            g: 90,
+                            b: 90,
+                        },
+                    ),
+                    Cell::Empty if reached.contains(&(x, y)) => (
+                        1,
+                        Rgb {
+                            r: 110,
+                            g: 110,
+                            b: 110,
+                        },
+                    ),
+                    Cell::Empty => (
+                        1,
+                        Rgb {
+                            r: 64,
+                            g: 64,
+                            b: 64,
+                        },
+                    ),
+                    Cell::Wall => (
+                        0,
+                        Rgb {
+                            r: 20,
+                            g: 20,
+                            b: 20,
+                        },
+                    ),
+                };
+                if r > rank || col == (Rgb { r: 0, g: 0, b: 0 }) {
+                    (rank, col) = (r, c);
+                }
+            }
+            col
+        };
+
+        let black = Rgb { r: 0, g: 0, b: 0 };
+        for y in top..top + height {
+            for x in left..left + width {
+                let (cx, cy) = (x - ml, y - mt);
+                let (upper, lower) = if (0..mw).contains(&cx) && (0..mh).contains(&cy) {
+                    (
+                        shade(cx * scale, 2 * cy * scale),
+                        shade(cx * scale, (2 * cy + 1) * scale),
+                    )
+                } else {
+                    (black, black)
+                };
+                screen.put(x, y, '▀', upper, lower);
+            }
+        }
+    }
+
+    fn move_player(&mut self, dx: i16, dy: i16) -> std::io::Result<Option<char>> {
+        let (xp, yp) = self.players[self.player];
+        let last = self.stats.last;
+        let key = self.step(dx, dy)?;
+        self.look();
+        if self.singleplayer && self.players[self.player] != (xp, yp) {
+            self.history.push(Move {
+                robot: self.player,
+                from: (xp, yp),
+                to: self.players[self.player],
+                key,
+                last,
+            });
+        }
+        Ok(key)
+    }
+
+    fn step(&mut self, dx: i16, dy: i16) -> std::io::Result<Option<char>> {
+        let (xp, yp) = self.players[self.player];
+        if xp + dx < 0 || yp + dy < 0 {
+            return Ok(None);
+        }
+        let target = self
+            .grid
             .cart
             .get((yp + dy) as usize)
             .and_then(|v| v.get((xp + dx) as usize));
@@ -1032,7 +3636,8 @@ impl GridState {
                     self.grid.cart[yp as usize][xp as usize] = Cell::Empty;
                     self.grid.cart[(yp + dy) as usize][(xp + dx) as usize] =
                         Cell::Player(self.player as i16);
-                    self.players[self.player] = (xp + dx, yp + dy)
+                    self.players[self.player] = (xp + dx, yp + dy);
+                    self.stats.moved(self.player);
                 }
                 Cell::Key(c) => {
                     let k = *c;
@@ -1040,6 +3645,14 @@ impl GridState {
                     self.grid.cart[(yp + dy) as usize][(xp + dx) as usize] =
                         Cell::Player(self.player as i16);
                     self.players[self.player] = (xp + dx, yp + dy);
+                    self.stats.moved(self.player);
+                    self.stats.keys[self.player] += 1;
+                    self.stats.route.push(Pickup {
+                        robot: self.player,
+                        key: k,
+                        steps: self.stats.total(),
+                        players: self.players,
+                    });
                     return Ok(Some(k));
                 }
             },
@@ -1047,12 +3660,125 @@ impl GridState {
         Ok(None)
     }
 
+    // puts back the key the last move picked up, and the door it opened, which is wherever it was in original
+    fn undo(&mut self, original: &Grid) {
+        let Some(m) = self.history.pop() else {
+            return;
+        };
+        let (x, y) = m.to;
+        self.grid.cart[y as usize][x as usize] = m.key.map_or(Cell::Empty, Cell::Key);
+        self.grid.cart[m.from.1 as usize][m.from.0 as usize] = Cell::Player(m.robot as i16);
+        self.players[m.robot] = m.from;
+        self.player = m.robot;
+
+        self.stats.steps[m.robot] -= 1;
+        if m.last.is_some_and(|l| l != m.robot) {
+            self.stats.switches -= 1;
+        }
+        self.stats.last = m.last;
+        if let Some(k) = m.key {
+            self.keys.remove(&k);
+            self.stats.keys[m.robot] -= 1;
+            self.stats.route.pop();
+            for (y, row) in original.cart.iter().enumerate() {
+                for (x, cell) in row.iter().enumerate() {
+                    if *cell == Cell::Door(k) {
+                        self.grid.cart[y][x] = Cell::Door(k);
+                    }
+                }
+            }
+        }
+        // finishing again gets a new report
+        self.finished = self.complete();
+        self.undone.push(m);
+    }
+
+    fn redo(&mut self) -> std::io::Result<()> {
+        let Some(m) = self.undone.pop() else {
+            return Ok(());
+        };
+        self.player = m.robot;
+        if let Some(k) = self.move_player(m.to.0 - m.from.0, m.to.1 - m.from.1)? {
+            self.use_key(&k);
+        }
+        Ok(())
+    }
+
+    fn complete(&self) -> bool {
+        self.all_keys.iter().all(|k| self.keys.contains(k))
+    }
+
     fn use_key(&mut self, c: &char) {
         let k = *c;
         self.keys.insert(k);
         self.remove_cell(Cell::Door(k));
     }
 
+    // a key whose location's been checked is gone, whoever on the slot it was that checked it
+    fn remove_checked(&mut self, locations: &[i64]) {
+        for l in locations {
+            if let Some(k) = self.key_from_id(*l) {
+                self.remove_cell(Cell::Key(k));
+            }
+        }
+    }
+
+    fn place_players(&mut self, players: [(i16, i16); 4]) {
+        for i in 0..4 {
+            self.remove_cell(Cell::Player(i));
+        }
+        self.players = players;
+        for ((x, y), n) in self.players.iter().zip(0..4) {
+            self.grid.cart[*y as usize][*x as usize] = Cell::Player(n);
+        }
+        self.look();
+    }
+
+    fn seen(&self, x: i16, y: i16) -> bool {
+        self.explored.as_ref().is_none_or(|e| e.contains(&(x, y)))
+    }
+
+    // in the fog, everything the robots can see in a straight line is explored
+    fn look(&mut self) {
+        let Some(explored) = &mut self.explored else {
+            return;
+        };
+        let see = 12;
+        let opaque = |x: i16, y: i16| {
+            self.grid
+                .cart
+                .get(y as usize)
+                .and_then(|row| row.get(x as usize))
+                .is_none_or(|c| matches!(c, Cell::Wall | Cell::Door(_)))
+        };
+        for (xp, yp) in self.players {
+            for (x, y) in
+                (yp - see..=yp + see).flat_map(|y| (xp - see..=xp + see).map(move |x| (x, y)))
+            {
+                if x < 0 || y < 0 || explored.contains(&(x, y)) {
+                    continue;
+                }
+                // the cells on the way there, not counting either end
+                let steps = (x - xp).abs().max((y - yp).abs());
+                let clear = (1..steps).all(|i| {
+                    let t = i as f64 / steps as f64;
+                    !opaque(
+                        xp + ((x - xp) as f64 * t).round() as i16,
+                        yp + ((y - yp) as f64 * t).round() as i16,
+                    )
+                });
+                let inside = self
+                    .grid
+                    .cart
+                    .get(y as usize)
+                    .is_some_and(|row| (x as usize) < row.len());
+                if clear && inside {
+                    explored.insert((x, y));
+                }
+            }
+        }
+    }
+
     fn remove_cell(&mut self, c: Cell) {
         for y in 0..self.grid.cart.len() {
             for x in 0..self.grid.cart[y].len() {
@@ -1067,6 +3793,55 @@ impl GridState {
         if !k.is_press() {
             return Ok(None);
         };
+        // any key stops a robot travelling by itself
+        if !self.travel.is_empty() {
+            self.travel.clear();
+            return Ok(None);
+        }
+        if let Some(input) = &mut self.goto {
+            match k.code {
+                KeyCode::Esc => self.goto = None,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Enter => {
+                    let input = input.clone();
+                    let target = input
+                        .split_once(',')
+                        .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)));
+                    match target
+                        .filter(|(x, y)| self.seen(*x, *y))
+                        .and_then(|to| self.path_to(to))
+                    {
+                        Some(path) => self.travel = path,
+                        None => {
+                            self.msgs.pop_front();
+                            self.msgs.push_back(RichPrint::message(format!(
+                                "Robot {} can't get to {input} from here.",
+                                self.player
+                            )));
+                        }
+                    }
+                    self.goto = None;
+                }
+                KeyCode::Char(c) if input.is_empty() && self.all_keys.contains(&c) => {
+                    let to = self
+                        .flood(self.players[self.player])
+                        .into_keys()
+                        .find(|(x, y)| {
+                            self.grid.cart[*y as usize][*x as usize] == Cell::Key(c)
+                                && self.seen(*x, *y)
+                        });
+                    if let Some(path) = to.and_then(|to| self.path_to(to)) {
+                        self.travel = path;
+                        self.goto = None;
+                    }
+                }
+                KeyCode::Char(c) if c.is_ascii_digit() || c == ',' || c == ' ' => input.push(c),
+                _ => {}
+            }
+            return Ok(None);
+        }
         match k.code {
             KeyCode::Char('q') => {
                 execute!(stdout(), Clear(ClearType::All), MoveTo(0, 0), Show)?;
@@ -1078,6 +3853,22 @@ impl GridState {
                 self.player %= 4;
                 Ok(None)
             }
+            KeyCode::Char('g') => {
+                self.goto = Some(String::new());
+                Ok(None)
+            }
+            KeyCode::Char('o') => {
+                self.overlay = !self.overlay;
+                Ok(None)
+            }
+            KeyCode::Char('v') => {
+                self.view = match self.view {
+                    View::Single => View::Split,
+                    View::Split => View::Map,
+                    View::Map => View::Single,
+                };
+                Ok(None)
+            }
             KeyCode::Char('w') | KeyCode::Up => self.move_player(0, -1),
             KeyCode::Char('a') | KeyCode::Left => self.move_player(-1, 0),
             KeyCode::Char('s') | KeyCode::Down => self.move_player(0, 1),
@@ -1086,12 +3877,517 @@ impl GridState {
         }
     }
 
-    fn add_items(&mut self, received: Vec<NetworkItem>) -> Result<(), StrError> {
+    // every cell reachable from a position through what's open now, mapped to the cell it's reached from
+    fn flood(&self, from: (i16, i16)) -> HashMap<(i16, i16), (i16, i16)> {
+        let mut came = HashMap::from([(from, from)]);
+        let mut queue = VecDeque::from([from]);
+        while let Some((x, y)) = queue.pop_front() {
+            for next in [(x, y - 1), (x + 1, y), (x, y + 1), (x - 1, y)] {
+                let open = self
+                    .grid
+                    .cart
+                    .get(next.1 as usize)
+                    .and_then(|row| row.get(next.0 as usize))
+                    .is_some_and(|c| !matches!(c, Cell::Wall | Cell::Door(_)));
+                if open && !came.contains_key(&next) {
+                    came.insert(next, (x, y));
+                    queue.push_back(next);
+                }
+            }
+        }
+        came
+    }
+
+    fn reachable_keys(&self) -> Vec<char> {
+        let reached = self.flood(self.players[self.player]);
+        let mut keys: Vec<char> = reached
+            .keys()
+            .filter(|(x, y)| self.seen(*x, *y))
+            .filter_map(|(x, y)| match self.grid.cart[*y as usize][*x as usize] {
+                Cell::Key(c) => Some(c),
+                _ => None,
+            })
+            .collect();
+        keys.sort_by_key(|c| self.all_keys.iter().position(|k| k == c));
+        keys
+    }
+
+    // the shortest way there for the active robot, not counting where it is now
+    fn path_to(&self, to: (i16, i16)) -> Option<VecDeque<(i16, i16)>> {
+        let from = self.players[self.player];
+        let came = self.flood(from);
+        let mut path = VecDeque::new();
+        let mut at = to;
+        while at != from {
+            path.push_front(at);
+            at = *came.get(&at)?;
+        }
+        Some(path)
+    }
+
+    // stops at the first key picked up on the way, so it's checked before anything after it
+    fn travel_step(&mut self) -> std::io::Result<Option<char>> {
+        let Some((x, y)) = self.travel.pop_front() else {
+            return Ok(None);
+        };
+        let (xp, yp) = self.players[self.player];
+        let key = self.move_player(x - xp, y - yp)?;
+        if key.is_some() || self.players[self.player] != (x, y) {
+            self.travel.clear();
+        }
+        Ok(key)
+    }
+
+    // the key an item or location id stands for, if it's one of this vault's
+    fn key_from_id(&self, id: i64) -> Option<char> {
+        u32::try_from(id)
+            .ok()
+            .and_then(char::from_u32)
+            .filter(|k| self.all_keys.contains(k))
+    }
+
+    // in any order and as often as the server likes, ignoring anything that isn't a key of
+    // this vault. gives back the items that brought a key that wasn't already held
+    fn add_items(&mut self, received: Vec<NetworkItem>) -> Vec<NetworkItem> {
+        let mut new = Vec::new();
         for item in received {
-            if let Some(x) = char::from_u32(item.item as u32) {
+            if let Some(x) = self.key_from_id(item.item) {
+                if !self.keys.contains(&x) {
+                    self.stats.arrivals.push((x, self.stats.route.len()));
+                    new.push(item);
+                }
                 self.use_key(&x);
             }
         }
-        Ok(())
+        new
+    }
+}
+
+// the random calls maze generation makes. ChaCha8Rng makes singleplayer vaults,
+// and PyRandom makes the same calls the way Create.py's python random does
+trait MazeRng {
+    fn below(&mut self, n: usize) -> usize;
+    fn coin(&mut self) -> bool;
+    fn chance(&mut self, percent: u8) -> bool;
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T>;
+    fn take<T>(&mut self, items: &mut Vec<T>) -> T; // removes a random item
+    fn shuffle<T>(&mut self, items: &mut [T]);
+    fn sample<'a, T>(&mut self, items: &'a [T], k: usize) -> Vec<&'a T>;
+}
+
+impl MazeRng for ChaCha8Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.random_range(0..n)
+    }
+
+    fn coin(&mut self) -> bool {
+        self.random_bool(0.5)
+    }
+
+    fn chance(&mut self, percent: u8) -> bool {
+        self.random_range(0..100) < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        items.choose(self)
+    }
+
+    fn take<T>(&mut self, items: &mut Vec<T>) -> T {
+        let i = self.random_range(0..items.len());
+        items.swap_remove(i)
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        SliceRandom::shuffle(items, self)
+    }
+
+    fn sample<'a, T>(&mut self, items: &'a [T], k: usize) -> Vec<&'a T> {
+        items.choose_multiple(self, k).collect()
+    }
+}
+
+// python's random.Random: a mersenne twister, plus random's own ways of turning its output into choices
+struct PyRandom {
+    state: [u32; 624],
+    index: usize,
+}
+
+impl PyRandom {
+    fn new(seed: u128) -> Self {
+        let mut r = PyRandom {
+            state: [0; 624],
+            index: 624,
+        };
+        r.state[0] = 19650218;
+        for i in 1..624 {
+            let prev = r.state[i - 1];
+            r.state[i] = 1812433253u32
+                .wrapping_mul(prev ^ (prev >> 30))
+                .wrapping_add(i as u32);
+        }
+
+        // the seed goes in as 32 bit words, least significant first
+        let mut key = Vec::new();
+        let mut rest = seed;
+        while rest > 0 || key.is_empty() {
+            key.push(rest as u32);
+            rest >>= 32;
+        }
+        let (mut i, mut j) = (1, 0);
+        for _ in 0..624.max(key.len()) {
+            let prev = r.state[i - 1];
+            r.state[i] = (r.state[i] ^ (prev ^ (prev >> 30)).wrapping_mul(1664525))
+                .wrapping_add(key[j])
+                .wrapping_add(j as u32);
+            i += 1;
+            j += 1;
+            if i >= 624 {
+                r.state[0] = r.state[623];
+                i = 1;
+            }
+            if j >= key.len() {
+                j = 0;
+            }
+        }
+        for _ in 0..623 {
+            let prev = r.state[i - 1];
+            r.state[i] = (r.state[i] ^ (prev ^ (prev >> 30)).wrapping_mul(1566083941))
+                .wrapping_sub(i as u32);
+            i += 1;
+            if i >= 624 {
+                r.state[0] = r.state[623];
+                i = 1;
+            }
+        }
+        r.state[0] = 0x80000000;
+        r
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        if self.index >= 624 {
+            for i in 0..624 {
+                let y = (self.state[i] & 0x80000000) | (self.state[(i + 1) % 624] & 0x7fffffff);
+                let mut next = self.state[(i + 397) % 624] ^ (y >> 1);
+                if y & 1 != 0 {
+                    next ^= 0x9908b0df;
+                }
+                self.state[i] = next;
+            }
+            self.index = 0;
+        }
+        let mut y = self.state[self.index];
+        self.index += 1;
+        y ^= y >> 11;
+        y ^= (y << 7) & 0x9d2c5680;
+        y ^= (y << 15) & 0xefc60000;
+        y ^ (y >> 18)
+    }
+
+    fn getrandbits(&mut self, k: u32) -> u128 {
+        // whole words come out least significant first, and the last one is cut down from the top
+        let mut bits = 0;
+        let mut done = 0;
+        while done < k {
+            let word = self.next_u32() >> (32 - (k - done).min(32));
+            bits |= (word as u128) << done;
+            done += 32;
+        }
+        bits
+    }
+
+    fn random(&mut self) -> f64 {
+        let a = (self.next_u32() >> 5) as f64;
+        let b = (self.next_u32() >> 6) as f64;
+        (a * 67108864.0 + b) / 9007199254740992.0
+    }
+}
+
+impl MazeRng for PyRandom {
+    fn below(&mut self, n: usize) -> usize {
+        let k = usize::BITS - n.leading_zeros();
+        loop {
+            let r = self.getrandbits(k) as usize;
+            if r < n {
+                return r;
+            }
+        }
+    }
+
+    fn coin(&mut self) -> bool {
+        self.random() < 0.5
+    }
+
+    fn chance(&mut self, percent: u8) -> bool {
+        self.below(100) < percent as usize
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            None
+        } else {
+            Some(&items[self.below(items.len())])
+        }
+    }
+
+    fn take<T>(&mut self, items: &mut Vec<T>) -> T {
+        let i = self.below(items.len());
+        items.remove(i)
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+
+    fn sample<'a, T>(&mut self, items: &'a [T], k: usize) -> Vec<&'a T> {
+        // python draws from a shrinking pool for small populations, and rejects repeats for large ones
+        let n = items.len();
+        let mut setsize = 21;
+        if k > 5 {
+            setsize += 4usize.pow(((k * 3) as f64).log(4.0).ceil() as u32);
+        }
+        if n <= setsize {
+            let mut pool: Vec<&T> = items.iter().collect();
+            (0..k)
+                .map(|i| {
+                    let j = self.below(n - i);
+                    let chosen = pool[j];
+                    pool[j] = pool[n - i - 1];
+                    chosen
+                })
+                .collect()
+        } else {
+            let mut selected = HashSet::new();
+            (0..k)
+                .map(|_| {
+                    let mut j = self.below(n);
+                    while !selected.insert(j) {
+                        j = self.below(n);
+                    }
+                    &items[j]
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // outputs from cpython's random for the same seeds
+    #[test]
+    fn matches_python_random() {
+        assert_eq!(PyRandom::new(0).random(), 0.8444218515250481);
+        assert_eq!(PyRandom::new(42).random(), 0.6394267984578837);
+        assert_eq!(
+            PyRandom::new(12345678901234567890123).random(),
+            0.15803717805068784
+        );
+
+        let mut rng = PyRandom::new(7);
+        assert_eq!(rng.getrandbits(64), 17485029721327973432);
+        assert_eq!(rng.getrandbits(64), 7283207964119141687);
+
+        // a small population is drawn from a shrinking pool, a large one by rejecting repeats
+        let small: Vec<usize> = (0..10).collect();
+        let large: Vec<usize> = (0..100).collect();
+        assert_eq!(PyRandom::new(1).sample(&small, 4), [&2, &1, &4, &0]);
+        assert_eq!(PyRandom::new(1).sample(&large, 4), [&17, &72, &97, &8]);
+
+        let mut shuffled = small.clone();
+        PyRandom::new(2).shuffle(&mut shuffled);
+        assert_eq!(shuffled, [5, 9, 3, 4, 6, 7, 2, 8, 1, 0]);
+
+        let mut rng = PyRandom::new(3);
+        let letters: Vec<char> = "abcdefg".chars().collect();
+        let chosen: String = (0..8).map(|_| *rng.pick(&letters).unwrap()).collect();
+        assert_eq!(chosen, "beebcedf");
+    }
+
+    // the vault Create.py gives the first player of multiworld seed 20250101 with the default options
+    #[test]
+    fn matches_create_py() {
+        let (grid, _) = Grid::generate_multiworld(20250101, 1, &GridOptions::default()).unwrap();
+        let expected = [
+            "#################################################################################",
+            "#...............#...........#.......Y...#...#.......#...........#...........#...#",
+            "###########.###.###.#####.#.#.#######.###.#.#####.#.#.#.###.#####.#######.#.#.#.#",
+            "#...........#.#...#.#.....#.#.#.m...#...#.#.....#.#.#.#.#...#...#.#.......#.#.#.#",
+            "#.###.#######.###.###.#######.#.#######.#.#####.###.#.#.#####.#.#.#.#######.#.#.#",
+            "#.#.V.#...#.....#.u.#...#...#...#.....#.#...#.....#...#.......#.#.#.....#.#...#.#",
+            "#.#####.#.###.#####.#.#.#.#.#.###.###.#.###.#####.#####.#######.#.#####.#.#####.#",
+            "#.......#...#.#...#.#.#...#...#...#.#.#.#...#...#.....#.t.#...#.....#...#.....#B#",
+            "#.#########.#.#.#.#.#######.###.###.#.#.#.#.#.#.#####.#.###.#.#######.#####.#.#.#",
+            "#.....#.....#...#.#.#.....#.#...#...#.#.#.#.#.#.#...#.#.#...#.....#...#.....#.#.#",
+            "#######.#########.#.#.###.#.#.###.#.#.#.#.###.#.###.#.###.#######.#.###.#.###.#.#",
+            "#.......#.........#...#...#.#...#.#...#.#.#...#...#.#.#...#...#.....#.#.#...#.#.#",
+            "#.#######.###.#########.###.###.#.#####.#.#.#####.#.#.#.#.#.#.#######.#.###.###.#",
+            "#.....#.....#.#.......#.#.....#.#...#...#...#.#...#.....#.#.#.....#.......#...#.#",
+            "#.###.#####.#.#.#.#####.#.#####.###.#.#.#.###.#.###########.#####.###########.#.#",
+            "#...#...#...#.#.#...#...#.#.#...#...#.#.#.....#.....#.....#.....#...#.......#.#.#",
+            "###.###.#.#####.###.#.###.#.#.###.#####.#####.#####.#.###.#####.###.#.#####.#.#.#",
+            "#.#.#...#.#.....#.#...#.....#.#...#.....#...#.....#...#.#.........#f..#...#.#c#.#",
+            "#.#.#.###.#.#####.###########v#.###.###.#.###.#####.###.###############.#.#.#.#.#",
+            "#...#.#.....#.#...............#.#...#.#.#.....#...#.......#.......#.....#...#...#",
+            "#.###.###.###.#.#####.#########.#.###.#.#.#####.#.#########.#####.#.#.#######.###",
+            "#.H.#...#.#.......#...#...........#.....#.#.....#...#.......#...#.#.#.#..K..#...#",
+            "###.###.#.#####.###.###.###########.#######.#######.#.#######.#.#.#.###.###.###.#",
+            "#.....#.#.....#.#...#.........#...#...#.#...#.....#...#.#...#.#...#.......#.#.#I#",
+            "#.#####.#####.###.###.#########.#.###.#.#.###.#.#######.#.#.#.#############.#F#.#",
+            "#.#.....#.........#...#.........#...#...#.#...#.........#.#...#...........#.#...#",
+            "#.#.#####.#############.###########.#####.#####.###.#####.#######.#.#####.#.#.###",
+            "#.#.#...#.#....W....#...#.........#....y#.#...#...#J......#.....#.#.#...#.#.#...#",
+            "###.#.###.#.#######.#.#####.#####.#####.#.#.#.###.#########.###.###.#.#.#.#.###.#",
+            "#...#.#...#...#...#...#.....#.......#...#...#...#.#...#...#.#.......#.#.#...#.#.#",
+            "#.###.#.#####.###.#######.###.#######.#########.#.#.#.#.#.#.#########.#.#####.#.#",
+            "#...#.....#.#...#.........#.#.#.#...#...#...#...#...#...#.#...#.......#...#...#.#",
+            "#.#.#####.#.###Q#######.###.#.#.#.#.###.#.#.#.###########.###.#.###.#####.#.#.#.#",
+            "#.#.#...#.....#.#.......#...#.#.#.#.....#.#...#.....#.#b..#...#...#...#...#.#.#.#",
+            "#.#.#.#.#####.#.#.#######i#.#.#.#.#####.#.#######.#.#.#.###.#####.###.#####.#.#.#",
+            "#.#.#.#.....#.#...#.......#.#...#.#.....#.#.......#...#.#...#...#.#.#.......#...#",
+            "#.#.#.#####.#######.###.###.###.#.#######.###.#######.#.#.###.###.#.#############",
+            "#.#.#.#.....#.....#.#.#.#.....#.#.#.....#...#.......#.#.#.#.#U..#.#.......#.....#",
+            "#.#.#.#.#####.###.#.#.#.#######.#.#.###.###.#.#####.###.#.#.#.#.#.#####.#.#.###.#",
+            "#.#.C.#.........#.....#.........#.....#@#@..#.....#...M...#...#......P..#...#...#",
+            "#################################################################################",
+            "#.#.............#.........#............@#@#.........#.....#.................#...#",
+            "#.#.#####.#####.#######.#.#.#############.#######.###.###.#.#.#############.#.#.#",
+            "#.#.#...#.....#.......#.#.#.....#.......#.....#...#...#.#...#.#.....#.........#.#",
+            "#.#.###.#####.#######.#.#######.#l#####.#####.#.###.###.#####.#.###.#############",
+            "#.#.......#...#.#...#.#.#.....#.#.#.....#s....#.....#.......#...#.#.........#...#",
+            "#.#######.#.###.#.#.#.#.#.###.#.#.#####.#.#########.#.#####.#####.#######.#.#.#.#",
+            "#.#.......#.#.....#.....#...#.#.#.#...#.#.#.......#...#..n#.#.......#...#.#.#.#.#",
+            "#.#.#######.###############.#.#.#.#.#.#.#.#.###.#.#####.#.#.#.#.#####.#.###.#.#.#",
+            "#...#.....#.#.......#.....#.#.#.#...#.#.#.#.#...#.....#.#.....#.#...#.#..T#...#.#",
+            "#.###.#####.#.#####.#.#.###.#.#.###.#.#.#.#.#.#######.#######.###.#.#.###.#####.#",
+            "#.#.j.#.....#.....#...#.....#.#...#.#.#.#.#.#.#...#.........#.#...#...#A#.......#",
+            "#.#.#.#.#########.###########.###.###.#.#.###.#.#.#########.###.#######.#######.#",
+            "#..E#.#.......#...#.........#...#.#...#.#...#...#.#.....#.#...#.......#.........#",
+            "#.###########.#.#######.#.###.#.#.#.###.###.#####.#.###.#.###.#.#####.#.#########",
+            "#.#...#.......#...#...#.#.#...#.#.#.#...#.#.....#...#...#...#.#.#...#.#.#.......#",
+            "###.#.#.###.#####.#.#.#.###.###.#.#.###.#.#####.#####.###.#.#.#.#.###.#.###.###.#",
+            "#...#...#...#...#...#...#...#...#.#...#.#.....#.....#.#...#.#.#.#.#...#...#.#.#.#",
+            "#.#########.#.#.#####.###.###.###.###.###.#########.#.#.#####.#.#.#.#####.#.#.#.#",
+            "#.#...#...#.#.#.....#.#.L.#.....#...#...#.........#...#.#....S#...#.#.....#...#.#",
+            "#.#.#.#.#.#.#.###.#x#.#.###########.###.#.#######.#####.#.#######.#.###.#####.#.#",
+            "#.#.#.#.#.#.#.#...#.#.#.....#.....#.....#.#.....#.....#...#.....#.#...#...#...#.#",
+            "#.#.#.#.#.###.#.#####.#####.#.#.#######.#.#.#########.#.#####.#X#####o###.#.###.#",
+            "#.#.#...#...#.#.......#.#...#.#.#.....#.#.#.........#...#.....#.......#p#.#...#.#",
+            "#.#.#######.#.#########.#.###.#.#.###.#.#.#.#######.#####.#####.#######.#.#####.#",
+            "#.#r#...#...#.#.........#.....#...#.#...#.#.#e#...#...#d..#.....#.....#.#.#...#.#",
+            "#.#.#.#.#.###.#.###.###.###########.#####.#.#.#.#.#.#.#.#########.###.#.#.#.#.#.#",
+            "#.#.#.#.#.#.a.#...#.#.#.#.......#...R...#.#.#.#.#...#.#...........#...#...#.#.#.#",
+            "#.#.###.#.#.#######.#.#.#.###.###.#####.#.#.#.#.#####g#############.###.###.#.#.#",
+            "#...#...#...#.......#.#.#...#.....#...#.#.#.#.#...#.#...............#.#.#...#.#.#",
+            "#.###.#######.#.#####.#.#######.###.#.#.#.#.#.###.#.#################.#.#.###.#.#",
+            "#...#.#.......#.#.....#.......#...#.#.#.#.#....N#.#.........#.....#...#...#.#.#.#",
+            "###z#.#.#######.###.#.#######.###.#.###.#.#.#####.#.#########.###.#.#.#####.#.#.#",
+            "#.#.#...#.......#...#.#...#...#.#.#.#...#.#.#...#.#.........#.#.#.#.#.......#...#",
+            "#.#.#####.#######.###.#.#.#.###.#.#.#.###.###.#.#.#.#######.#.#.#.#.###.###.###.#",
+            "#.#.......#.......#.....#.#.#.......#...#.....#...#.#...G.#.#...#.#...#.#...#...#",
+            "#.#########.#######.#######.#######.###.###########.###.#.#.###.#w###.#.#####.###",
+            "#...#........D#.......#.....#..k#...#...#.........#...#.#.#...#.#.....#.#.....#.#",
+            "#.###.#######.#########.#####.#.#####.#.#.###.#######.#.#.#.###.#######.#.#####.#",
+            "#...........#.................#.......#.#...#..h...q....#.#.O.........#........Z#",
+            "#################################################################################",
+        ];
+        assert_eq!(
+            grid.to_input(),
+            expected.map(|row| row.to_string() + "\n").concat()
+        );
+    }
+
+    // the examples from part 2 of the puzzle, with their answers
+    #[test]
+    fn solves_the_examples() {
+        let examples = [
+            (
+                "#######\n#a.#Cd#\n##@#@##\n#######\n##@#@##\n#cB#Ab#\n#######",
+                8,
+            ),
+            (
+                "###############\n#d.ABC.#.....a#\n######@#@######\n###############\n\
+                 ######@#@######\n#b.....#.....c#\n###############",
+                24,
+            ),
+            (
+                "#############\n#DcBa.#.GhKl#\n#.###@#@#I###\n#e#d#####j#k#\n\
+                 ###C#@#@###J#\n#fEbA.#.FgHi#\n#############",
+                32,
+            ),
+            (
+                "#############\n#g#f.D#..h#l#\n#F###e#E###.#\n#dCba@#@BcIJ#\n\
+                 #############\n#nK.L@#@G...#\n#M###N#H###.#\n#o#m..#i#jk.#\n#############",
+                72,
+            ),
+        ];
+        for (input, steps) in examples {
+            let grid = Grid::from_input(input).unwrap();
+            let solution = grid.solve(SOLVE_LIMIT).unwrap().unwrap();
+            assert_eq!(solution.steps, steps);
+        }
+    }
+
+    // the first example as part 1 gives it, which part 2 splits into the four vaults above
+    #[test]
+    fn splits_the_entrance() {
+        let grid =
+            Grid::from_input("#######\n#a.#Cd#\n##...##\n##.@.##\n##...##\n#cB#Ab#\n#######")
+                .unwrap();
+        let split =
+            Grid::from_input("#######\n#a.#Cd#\n##@#@##\n#######\n##@#@##\n#cB#Ab#\n#######")
+                .unwrap();
+        assert_eq!(grid.to_input(), split.to_input());
+        assert_eq!(grid.robots(), split.robots());
+        assert_eq!(grid.solve(SOLVE_LIMIT).unwrap().unwrap().steps, 8);
+    }
+
+    // a loop round the middle of a vault, reached through a single gap at the bottom. key c can be
+    // reached through door a on the left or doors b and d on the right
+    #[test]
+    fn follows_loops() {
+        let grid = Grid {
+            cart: [
+                "#######", "#.Ac.D#", "#.###.#", "#.###B#", "#.....#", "###.###", "###0###",
+                "#######",
+            ]
+            .iter()
+            .map(|row| row.chars().map(|c| Cell::to_cell(c).unwrap()).collect())
+            .collect(),
+            tree: HashMap::new(),
+        };
+        let starts = [(3, 6)];
+        let dominators = grid.dominators(&starts);
+        // the way round past doors b and d means door a doesn't keep key c behind it,
+        // but the gap does
+        assert!(!Grid::dominates(&dominators, (2, 1), (3, 1)));
+        assert!(Grid::dominates(&dominators, (3, 5), (3, 1)));
+        assert!(!Grid::dominates(&dominators, (3, 1), (3, 5)));
+
+        let keymap = KeyMap::from([('c', (3, 1))]);
+        let doormap = DoorMap::from([((2, 1), 'a'), ((5, 3), 'b'), ((5, 1), 'd')]);
+        let routes = grid.door_routes(&starts, &doormap);
+        assert_eq!(routes[1][3].len(), 2);
+        assert_eq!(grid.door_logic(&starts, &keymap, &doormap)[&'c'], ['a']);
+    }
+
+    #[test]
+    fn rejects_broken_maps() {
+        let broken = [
+            // ragged
+            "#######\n#a.#Cd#\n##@#@##\n#######\n##@#@##\n#cB#Ab#\n#####",
+            // two of key a
+            "#######\n#a.#Cd#\n##@#@##\n#######\n##@#@##\n#cB#Aa#\n#######",
+            // door B with no key b
+            "#######\n#a.#Cd#\n##@#@##\n#######\n##@#@##\n#cB#A.#\n#######",
+            // a digit is another robot 0
+            "#######\n#a.#Cd#\n##@#@##\n#######\n##@#@##\n#cB#Ab#\n####0##",
+        ];
+        for input in broken {
+            assert!(Grid::from_input(input).is_err(), "{input}");
+        }
     }
 }